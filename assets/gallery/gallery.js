@@ -0,0 +1,183 @@
+'use strict';
+
+const form = document.getElementById('filters');
+const framesElement = document.getElementById('frames');
+const summaryElement = document.getElementById('summary');
+const svgNamespace = 'http://www.w3.org/2000/svg';
+
+// Metadata of the frames of each run which was loaded so far, by the name of
+// the directory and the run separated by a slash.
+const runs = new Map();
+
+async function fetchJson(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(`${url} responded with ${response.status}`);
+  }
+
+  return response.json();
+}
+
+async function frames(key) {
+  if (!runs.has(key)) {
+    const [name, run] = key.split('/');
+    runs.set(key, await fetchJson(`/highlights/${name}?run=${run}`));
+  }
+
+  return runs.get(key);
+}
+
+// File name of the frame without extension, which identifies it in the URLs.
+function stem(frame) {
+  return frame.replace(/\.[^.]*$/, '');
+}
+
+function filters() {
+  return {
+    frame: form.frame.value.trim(),
+    minSaliency: Number(form.minSaliency.value),
+    minArea: Number(form.minArea.value) || 0,
+    maxArea: Number(form.maxArea.value) || Infinity,
+    crops: form.crops.checked,
+  };
+}
+
+function passes(highlight, filter) {
+  const area = highlight.rect.width * highlight.rect.height;
+
+  return highlight.saliency >= filter.minSaliency
+    && area >= filter.minArea
+    && area <= filter.maxArea;
+}
+
+function element(tag, attributes = {}, children = []) {
+  const node = tag === 'svg' || tag === 'rect'
+    ? document.createElementNS(svgNamespace, tag)
+    : document.createElement(tag);
+
+  for (const [key, value] of Object.entries(attributes)) {
+    node.setAttribute(key, value);
+  }
+  for (const child of children) {
+    node.append(child);
+  }
+
+  return node;
+}
+
+// Selecting a rectangle selects its crop and the other way around.
+function select(panel, index) {
+  for (const node of panel.querySelectorAll('.selected')) {
+    node.classList.remove('selected');
+  }
+  for (const node of panel.querySelectorAll(`[data-index="${index}"]`)) {
+    node.classList.add('selected');
+  }
+}
+
+function renderPanel(key, frame, filter) {
+  if (!frame) {
+    return element('div', { class: 'panel missing' }, [`${key}: frame was not processed`]);
+  }
+
+  const [name, run] = key.split('/');
+  const base = `/highlights/${name}/${stem(frame.frame)}`;
+  const visible = frame.highlights
+    .map((highlight, index) => ({ highlight, index }))
+    .filter(({ highlight }) => passes(highlight, filter));
+
+  const panel = element('div', { class: 'panel' }, [
+    element('h2', {}, [
+      `${key} / ${frame.frame} — cell size ${frame.cell_size}`
+        + ` — ${visible.length} of ${frame.highlights.length} highlights`,
+    ]),
+  ]);
+
+  const svg = element('svg', {
+    viewBox: `0 0 ${frame.width} ${frame.height}`,
+    preserveAspectRatio: 'none',
+  });
+  for (const { highlight, index } of visible) {
+    const { x, y, width, height } = highlight.rect;
+    const rect = element('rect', { x, y, width, height, 'data-index': index });
+    const title = document.createElementNS(svgNamespace, 'title');
+    title.textContent = `#${index} saliency ${highlight.saliency.toFixed(3)}`;
+    rect.append(title);
+    rect.addEventListener('click', () => select(panel, index));
+    svg.append(rect);
+  }
+
+  panel.append(element('div', { class: 'overlay' }, [
+    element('img', { src: `${base}/original?run=${run}`, loading: 'lazy', alt: frame.frame }),
+    svg,
+  ]));
+
+  if (filter.crops) {
+    const crops = element('div', { class: 'crops' });
+    for (const { highlight, index } of visible) {
+      const figure = element('figure', { 'data-index': index }, [
+        element('img', { src: `${base}/${index}?run=${run}`, loading: 'lazy', alt: `#${index}` }),
+        element('figcaption', {}, [`#${index} ${highlight.saliency.toFixed(2)}`]),
+      ]);
+      figure.addEventListener('click', () => select(panel, index));
+      crops.append(figure);
+    }
+    panel.append(crops);
+  }
+
+  return panel;
+}
+
+async function render() {
+  const filter = filters();
+  const left = form.left.value;
+  const right = form.right.value;
+  form.minSaliencyValue.value = filter.minSaliency.toFixed(2);
+
+  if (!left) {
+    summaryElement.textContent = 'No directory was processed yet.';
+    framesElement.replaceChildren();
+    return;
+  }
+
+  const leftFrames = await frames(left);
+  const rightFrames = right ? await frames(right) : [];
+  const rightByName = new Map(rightFrames.map((frame) => [frame.frame, frame]));
+
+  const shown = leftFrames.filter((frame) => frame.frame.includes(filter.frame));
+  framesElement.replaceChildren(...shown.map((frame) => {
+    const row = element('section', { class: 'frame' }, [renderPanel(left, frame, filter)]);
+    if (right) {
+      row.append(renderPanel(right, rightByName.get(frame.frame), filter));
+    }
+
+    return row;
+  }));
+
+  summaryElement.textContent = `${shown.length} of ${leftFrames.length} frames`;
+}
+
+async function start() {
+  const list = await fetchJson('/highlights');
+
+  for (const directory of list) {
+    // The latest run is listed first, as it is the one most likely reviewed.
+    for (const run of [...directory.runs].reverse()) {
+      const key = `${directory.name}/${run}`;
+      const label = run === directory.runs[directory.runs.length - 1]
+        ? `${key} (${directory.frames} frames, ${directory.highlights} highlights)`
+        : key;
+      form.left.append(element('option', { value: key }, [label]));
+      form.right.append(element('option', { value: key }, [label]));
+    }
+  }
+
+  form.addEventListener('input', () => render().catch(showError));
+  await render();
+}
+
+function showError(error) {
+  summaryElement.textContent = error.message;
+}
+
+start().catch(showError);
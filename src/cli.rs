@@ -0,0 +1,91 @@
+use conf;
+use highlights::{self, Settings, IOU_THRESHOLD};
+use rayon::ThreadPoolBuilder;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+pub const EVALUATE_USAGE: &str = concat!(
+    "Usage: harriet-vision-nursery evaluate <images> <labels> ",
+    "[--settings <file>] [--iou <threshold>] [--output <file>]\n\n",
+    "Runs the pipeline on the labelled images in the <images> directory and ",
+    "scores the highlights against the boxes in <labels>, which is either a ",
+    "COCO file or a directory of Pascal VOC files. The report is written to ",
+    "evaluation.json unless --output is given."
+);
+
+/// Scores the pipeline against labelled boxes and writes a JSON report, so that
+/// changes to the pipeline can be compared on a fixed set of images. Returns
+/// the reason of the failure.
+pub fn evaluate(args: &[String]) -> Result<(), String> {
+    let mut positional: Vec<&String> = Vec::new();
+    let mut settings = Settings::default();
+    let mut iou_threshold = IOU_THRESHOLD;
+    let mut output = PathBuf::from("evaluation.json");
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Option {} has no value.", arg))
+        };
+
+        match arg.as_str() {
+            "--settings" => {
+                let path = value()?;
+                let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+                settings = serde_json::from_reader(file)
+                    .map_err(|error| format!("Invalid settings: {}", error))?;
+            }
+            "--iou" => {
+                iou_threshold = value()?
+                    .parse()
+                    .ok()
+                    .filter(|threshold| *threshold > 0.0 && *threshold <= 1.0)
+                    .ok_or("Threshold has to be between 0 and 1.")?;
+            }
+            "--output" => output = PathBuf::from(value()?),
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown option {}.", option));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let (images, labels) = match positional.as_slice() {
+        [images, labels] => (Path::new(images.as_str()), Path::new(labels.as_str())),
+        _ => return Err("Expected the images and the labels.".to_string()),
+    };
+    settings.validate()?;
+
+    let labels = highlights::read_labels(labels)
+        .map_err(|error| format!("Cannot read labels {:?}: {}", labels, error))?;
+    // The frames are processed on as many threads as the worker of the
+    // service has.
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(conf::worker_threads())
+        .build()
+        .map_err(|error| format!("Cannot build thread pool: {}", error))?;
+    let report = pool
+        .install(|| highlights::evaluate(images, &labels, &settings, iou_threshold))
+        .map_err(|error| format!("Cannot evaluate {:?}: {}", images, error))?;
+
+    for frame in report.missing.iter() {
+        println!("Labelled frame {} is missing.", frame);
+    }
+    let scores = &report.dataset;
+    println!(
+        "{} frames: precision {:.3}, recall {:.3}, F1 {:.3}, mean IoU {:.3}.",
+        report.frames.len(),
+        scores.precision,
+        scores.recall,
+        scores.f1,
+        scores.mean_iou
+    );
+
+    let file = File::create(&output).map_err(|error| format!("{:?}: {}", output, error))?;
+    serde_json::to_writer_pretty(file, &report)
+        .map_err(|error| format!("Cannot write report: {}", error))?;
+    println!("Report written to {:?}.", output);
+
+    Ok(())
+}
@@ -1,20 +1,64 @@
+use highlights::{validate_schedule, REWATCH_SCHEDULE};
 use std::env;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct ServerConf {
     input_path: String,
     output_path: String,
     worker_threads_n: usize,
+    rewatch_schedule: Vec<u32>,
+    watcher: Option<WatcherConf>,
+}
+
+/// How the input root is watched for new directories.
+#[derive(Clone)]
+pub struct WatcherConf {
+    /// Directories without a `.done` marker are processed once nothing in them
+    /// was written for this long.
+    pub quiescence: Duration,
+
+    /// Time between two scans of the input root.
+    pub interval: Duration,
 }
 
 impl ServerConf {
     pub fn new() -> Self {
         let input_path = env::var("INPUT").expect("Env var INPUT is missing.");
         let output_path = env::var("OUTPUT").expect("Env var OUTPUT is missing.");
-        let worker_threads_n: usize = env::var("WORKER_THREADS")
-            .ok()
-            .and_then(|val| val.parse().ok())
-            .unwrap_or_else(|| num_cpus::get());
+        let worker_threads_n = worker_threads();
+        let rewatch_schedule: Vec<u32> = match env::var("REWATCH_SCHEDULE") {
+            Err(_) => REWATCH_SCHEDULE.to_vec(),
+            Ok(val) => val
+                .split(',')
+                .map(|size| size.trim().parse())
+                .collect::<Result<_, _>>()
+                .expect("Env var REWATCH_SCHEDULE is not a list of cell sizes."),
+        };
+        if let Err(error) = validate_schedule(&rewatch_schedule) {
+            panic!("Env var REWATCH_SCHEDULE is invalid: {}", error);
+        }
+
+        let watcher = if env::var("WATCH_INPUT").ok().as_deref() == Some("true") {
+            let seconds = |var: &str, default: u64| {
+                let seconds = env::var(var)
+                    .ok()
+                    .map(|val| {
+                        val.parse().unwrap_or_else(|_| {
+                            panic!("Env var {} is not a number of seconds.", var)
+                        })
+                    })
+                    .unwrap_or(default);
+                Duration::from_secs(seconds)
+            };
+
+            Some(WatcherConf {
+                quiescence: seconds("WATCH_QUIESCENCE", 30),
+                interval: seconds("WATCH_INTERVAL", 5),
+            })
+        } else {
+            None
+        };
 
         println!(
             concat!(
@@ -28,11 +72,12 @@ impl ServerConf {
             input_path,
             output_path,
             worker_threads_n,
+            rewatch_schedule,
+            watcher,
         }
     }
 
     /// Absolute path of the system directory to which the artifacts are stored.
-    #[allow(dead_code)]
     pub fn output_path(&self) -> &str {
         &self.output_path
     }
@@ -47,4 +92,25 @@ impl ServerConf {
     pub fn worker_threads(&self) -> usize {
         self.worker_threads_n
     }
+
+    /// Cell sizes of the consecutive passes over a directory which is
+    /// re-watched. The last one is used for all further passes.
+    pub fn rewatch_schedule(&self) -> &[u32] {
+        &self.rewatch_schedule
+    }
+
+    /// New directories in the input root are processed automatically if set.
+    pub fn watcher(&self) -> Option<&WatcherConf> {
+        self.watcher.as_ref()
+    }
+}
+
+/// Number of threads which process the images, given by the `WORKER_THREADS`
+/// env var or the number of CPUs. Subcommands use it as well, so that they
+/// behave like the service.
+pub fn worker_threads() -> usize {
+    env::var("WORKER_THREADS")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or_else(num_cpus::get)
 }
@@ -0,0 +1,18 @@
+use rocket::response::content::{Css, Html, JavaScript};
+
+/// Page of the gallery. It loads everything else from the server, so that it
+/// works without access to the internet.
+#[get("/")]
+pub fn index() -> Html<&'static str> {
+    Html(include_str!("../assets/gallery/index.html"))
+}
+
+#[get("/gallery.js")]
+pub fn script() -> JavaScript<&'static str> {
+    JavaScript(include_str!("../assets/gallery/gallery.js"))
+}
+
+#[get("/gallery.css")]
+pub fn style() -> Css<&'static str> {
+    Css(include_str!("../assets/gallery/gallery.css"))
+}
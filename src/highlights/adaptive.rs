@@ -0,0 +1,168 @@
+use super::heat_map::validate_cell_size;
+use serde::{Deserialize, Serialize};
+
+/// Smallest and largest cell size the search tries by default.
+pub const MIN_CELL_SIZE: u32 = 6;
+pub const MAX_CELL_SIZE: u32 = 20;
+
+/// Searches the cell size for each frame instead of using the same one for the
+/// whole directory. Busy frames end up with larger cells and sparse frames with
+/// smaller ones. The search starts at the cell size of the settings and walks
+/// towards the band until the frame falls within it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adaptive {
+    /// Range of the cell sizes the search can choose from.
+    pub min_cell_size: u32,
+    pub max_cell_size: u32,
+
+    /// Number of highlights a frame should have.
+    pub highlights: Option<Band>,
+
+    /// Fraction of the cells of the heat map the highlights should cover.
+    pub coverage: Option<Band>,
+}
+
+/// Inclusive range of values the search aims for.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Band {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Cell size the search tried and what the frame looked like with it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Candidate {
+    pub cell_size: u32,
+    pub highlights: usize,
+    pub coverage: f64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            min_cell_size: MIN_CELL_SIZE,
+            max_cell_size: MAX_CELL_SIZE,
+            highlights: None,
+            coverage: None,
+        }
+    }
+}
+
+impl Adaptive {
+    /// Returns the reason why the search cannot run with these parameters.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_cell_size(self.min_cell_size)?;
+        validate_cell_size(self.max_cell_size)?;
+
+        if self.min_cell_size > self.max_cell_size {
+            return Err("Smallest cell size is larger than the largest one.".to_string());
+        }
+
+        if self.highlights.is_none() && self.coverage.is_none() {
+            return Err("Adaptive cell size needs a band of highlights or coverage.".to_string());
+        }
+
+        for band in self.highlights.iter().chain(self.coverage.iter()) {
+            if !(band.min >= 0.0 && band.min <= band.max) {
+                return Err(format!(
+                    "Band from {} to {} is not a range.",
+                    band.min, band.max
+                ));
+            }
+        }
+
+        if let Some(coverage) = &self.coverage {
+            if coverage.max > 1.0 {
+                return Err("Coverage cannot be larger than 1.".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the pipeline with cell sizes from given one in steps of two until
+    /// the frame falls within the bands, the search runs out of the range or
+    /// overshoots the bands. In the last case the candidate closest to the
+    /// bands wins. Returns the result of the pipeline with the chosen cell size
+    /// and all candidates in the order they were tried.
+    pub fn search<T>(
+        &self,
+        start: u32,
+        mut run: impl FnMut(u32) -> (Candidate, T),
+    ) -> (T, Vec<Candidate>) {
+        let mut cell_size = start.max(self.min_cell_size).min(self.max_cell_size);
+        let mut tried: Vec<(Candidate, T)> = Vec::new();
+        // Larger cells merge objects together, therefore the search only ever
+        // walks in the direction the first candidate asked for.
+        let mut direction = 0;
+
+        loop {
+            let (candidate, result) = run(cell_size);
+            let step = self.direction(&candidate);
+            tried.push((candidate, result));
+
+            if step == 0 || (direction != 0 && step != direction) {
+                break;
+            }
+            direction = step;
+
+            let next = cell_size as i64 + 2 * step;
+            if next < self.min_cell_size as i64 || next > self.max_cell_size as i64 {
+                break;
+            }
+            cell_size = next as u32;
+        }
+
+        // The first of the candidates which are equally close wins.
+        let mut best = 0;
+        for (index, (candidate, _)) in tried.iter().enumerate() {
+            if self.distance(candidate) < self.distance(&tried[best].0) {
+                best = index;
+            }
+        }
+
+        let candidates = tried
+            .iter()
+            .map(|(candidate, _)| candidate.clone())
+            .collect();
+        (tried.swap_remove(best).1, candidates)
+    }
+
+    /// Whether the cells should grow, which is one, shrink, which is minus one,
+    /// or stay as they are.
+    fn direction(&self, candidate: &Candidate) -> i64 {
+        let measures = self.measures(candidate);
+        if measures.iter().any(|(value, band)| *value > band.max) {
+            1
+        } else if measures.iter().any(|(value, band)| *value < band.min) {
+            -1
+        } else {
+            0
+        }
+    }
+
+    /// How far outside of the bands the candidate is, relative to the width of
+    /// each band.
+    fn distance(&self, candidate: &Candidate) -> f64 {
+        self.measures(candidate)
+            .iter()
+            .map(|(value, band)| {
+                let outside = (band.min - value).max(value - band.max).max(0.0);
+                outside / (band.max - band.min).max(1e-3)
+            })
+            .sum()
+    }
+
+    fn measures(&self, candidate: &Candidate) -> Vec<(f64, Band)> {
+        let mut measures = Vec::new();
+        if let Some(band) = self.highlights {
+            measures.push((candidate.highlights as f64, band));
+        }
+        if let Some(band) = self.coverage {
+            measures.push((candidate.coverage, band));
+        }
+        measures
+    }
+}
@@ -0,0 +1,175 @@
+use super::grid::Rect;
+use super::image::{imageops, DynamicImage, FilterType, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+/// Fixed size the highlights are resized to, so that consumers of the output do
+/// not have to do it themselves.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanonicalSize {
+    pub width: u32,
+    pub height: u32,
+
+    #[serde(default)]
+    pub strategy: ResizeStrategy,
+
+    #[serde(default)]
+    pub filter: ResizeFilter,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeStrategy {
+    /// Scales the highlight to fit the size and pads the rest with black, or
+    /// with transparent pixels if the highlight is masked.
+    #[default]
+    Letterbox,
+    /// Grows the highlight to the aspect ratio of the size with the pixels
+    /// around it in the original image. Pads only what does not fit the image.
+    Context,
+    /// Scales the highlight to cover the size and cuts off what overflows.
+    CenterCrop,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> FilterType {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Maps positions in the original image to positions in the persisted
+/// highlight. A pixel at `x` in the original image ends up at
+/// `(x - source.x) * scale_x + offset_x` in the highlight, and the same goes for
+/// the y axis.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Transform {
+    /// Part of the original image the highlight was cut from.
+    pub source: Rect,
+    pub scale_x: f64,
+    pub scale_y: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+}
+
+impl Transform {
+    /// The highlight is the source rectangle as it is.
+    pub fn identity(source: Rect) -> Self {
+        Self {
+            source,
+            scale_x: 1_f64,
+            scale_y: 1_f64,
+            offset_x: 0_f64,
+            offset_y: 0_f64,
+        }
+    }
+}
+
+/// Grows the rectangle to the aspect ratio of the canonical size around its
+/// centre. The result is shifted and clamped so that it stays within the image.
+pub fn expand_to_aspect_ratio(
+    rect: Rect,
+    size: &CanonicalSize,
+    (width, height): (u32, u32),
+) -> Rect {
+    let aspect_ratio = size.width as f64 / size.height as f64;
+
+    // Grows one side, keeping the centre of the rectangle where it was.
+    let grow = |start: u32, length: u32, target: f64, limit: u32| -> (u32, u32) {
+        let target = (target.round() as u32).max(length).min(limit);
+        let start = start.saturating_sub((target - length) / 2);
+
+        (start.min(limit - target), target)
+    };
+
+    if (rect.width as f64 / rect.height as f64) < aspect_ratio {
+        let (x, width) = grow(rect.x, rect.width, rect.height as f64 * aspect_ratio, width);
+        Rect { x, width, ..rect }
+    } else {
+        let (y, height) = grow(
+            rect.y,
+            rect.height,
+            rect.width as f64 / aspect_ratio,
+            height,
+        );
+        Rect { y, height, ..rect }
+    }
+}
+
+/// Resizes the highlight to the canonical size with given filter. The filter is
+/// a parameter so that masks can be resized with the same geometry but without
+/// interpolation. Returns the resized image and the transform from the source
+/// rectangle.
+pub fn canonical_size(
+    image: &DynamicImage,
+    source: Rect,
+    size: &CanonicalSize,
+    filter: FilterType,
+) -> (DynamicImage, Transform) {
+    let (width, height) = image.dimensions();
+    let scale_x = size.width as f64 / width as f64;
+    let scale_y = size.height as f64 / height as f64;
+
+    // Letterbox fits the image in, center crop makes it cover the whole size.
+    let scale = match size.strategy {
+        ResizeStrategy::Letterbox | ResizeStrategy::Context => scale_x.min(scale_y),
+        ResizeStrategy::CenterCrop => scale_x.max(scale_y),
+    };
+    let resized_width = ((width as f64 * scale).round() as u32).max(1);
+    let resized_height = ((height as f64 * scale).round() as u32).max(1);
+    let mut resized = image.resize_exact(resized_width, resized_height, filter);
+
+    let (output, offset_x, offset_y) = if size.strategy == ResizeStrategy::CenterCrop {
+        let x = resized_width.saturating_sub(size.width) / 2;
+        let y = resized_height.saturating_sub(size.height) / 2;
+        let output = resized.crop(x, y, size.width, size.height);
+
+        (output, 0_f64 - x as f64, 0_f64 - y as f64)
+    } else {
+        let x = (size.width - resized_width.min(size.width)) / 2;
+        let y = (size.height - resized_height.min(size.height)) / 2;
+        let mut canvas = blank_like(image, size.width, size.height);
+        imageops::replace(&mut canvas, &resized, x, y);
+
+        (canvas, x as f64, y as f64)
+    };
+
+    let transform = Transform {
+        source,
+        scale_x: resized_width as f64 / width as f64,
+        scale_y: resized_height as f64 / height as f64,
+        offset_x,
+        offset_y,
+    };
+
+    (output, transform)
+}
+
+/// Creates a black image of the same colour type as the given one. Images with
+/// alpha channel get a transparent one.
+fn blank_like(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    match image {
+        DynamicImage::ImageLuma8(_) => DynamicImage::new_luma8(width, height),
+        DynamicImage::ImageLumaA8(_) => DynamicImage::new_luma_a8(width, height),
+        DynamicImage::ImageRgba8(_) | DynamicImage::ImageBgra8(_) => {
+            DynamicImage::new_rgba8(width, height)
+        }
+        _ => DynamicImage::new_rgb8(width, height),
+    }
+}
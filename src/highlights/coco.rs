@@ -0,0 +1,220 @@
+use super::grid::Rect;
+use super::labels::Labels;
+use super::metadata::FrameMetadata;
+use super::shape_descriptors::polygon_area;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// All highlights belong to the same category, because the objects are not
+/// classified.
+const CATEGORY_ID: u32 = 1;
+
+/// Annotations in the COCO object detection format.
+#[derive(Serialize)]
+struct Dataset {
+    images: Vec<Image>,
+    annotations: Vec<Annotation>,
+    categories: Vec<Category>,
+}
+
+#[derive(Serialize)]
+struct Image {
+    id: usize,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct Annotation {
+    id: usize,
+    image_id: usize,
+    category_id: u32,
+    /// Rectangle as `[x, y, width, height]` in pixels.
+    bbox: [u32; 4],
+    /// Contour of the object as a single polygon of alternating x and y.
+    segmentation: Vec<Vec<u32>>,
+    /// Area of the polygon in pixels.
+    area: f64,
+    iscrowd: u8,
+    /// Not part of the format, tools which do not know it ignore it.
+    score: f32,
+}
+
+#[derive(Serialize)]
+struct Category {
+    id: u32,
+    name: &'static str,
+    supercategory: &'static str,
+}
+
+/// Writes the frames as a COCO dataset into a single JSON file. Ids of images
+/// and annotations start at 1, because some tools treat 0 as missing.
+pub fn write(frames: &[FrameMetadata], path: &Path) -> io::Result<()> {
+    let mut dataset = Dataset {
+        images: Vec::new(),
+        annotations: Vec::new(),
+        categories: vec![Category {
+            id: CATEGORY_ID,
+            name: "highlight",
+            supercategory: "object",
+        }],
+    };
+
+    for (image_index, frame) in frames.iter().enumerate() {
+        let image_id = image_index + 1;
+        dataset.images.push(Image {
+            id: image_id,
+            file_name: frame.frame.clone(),
+            width: frame.width,
+            height: frame.height,
+        });
+
+        for highlight in frame.highlights.iter() {
+            let rect = highlight.rect;
+            let polygon = highlight
+                .contour
+                .iter()
+                .flat_map(|corner| vec![corner.x, corner.y])
+                .collect();
+
+            dataset.annotations.push(Annotation {
+                id: dataset.annotations.len() + 1,
+                image_id,
+                category_id: CATEGORY_ID,
+                bbox: [rect.x, rect.y, rect.width, rect.height],
+                segmentation: vec![polygon],
+                area: polygon_area(&highlight.contour),
+                iscrowd: 0,
+                score: highlight.saliency,
+            });
+        }
+    }
+
+    serde_json::to_writer(File::create(path)?, &dataset)?;
+    Ok(())
+}
+
+/// Subset of a COCO dataset which is needed to read labelled boxes. Other
+/// fields are ignored.
+#[derive(Deserialize)]
+struct LabelledDataset {
+    images: Vec<LabelledImage>,
+    #[serde(default)]
+    annotations: Vec<LabelledAnnotation>,
+}
+
+#[derive(Deserialize)]
+struct LabelledImage {
+    id: u64,
+    file_name: String,
+}
+
+#[derive(Deserialize)]
+struct LabelledAnnotation {
+    image_id: u64,
+    /// Rectangle as `[x, y, width, height]` in pixels.
+    bbox: [f64; 4],
+}
+
+/// Reads the boxes of all categories from a COCO dataset, by the file name of
+/// their image. Images without annotations are labelled with no boxes.
+pub fn read(path: &Path) -> io::Result<Labels> {
+    let dataset: LabelledDataset = serde_json::from_reader(File::open(path)?)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let mut labels = Labels::new();
+    let mut names: HashMap<u64, String> = HashMap::new();
+    for image in dataset.images {
+        // Datasets often keep the images in a subdirectory.
+        let name = Path::new(&image.file_name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&image.file_name)
+            .to_string();
+        labels.entry(name.clone()).or_default();
+        names.insert(image.id, name);
+    }
+
+    for annotation in dataset.annotations {
+        let name = names.get(&annotation.image_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Annotation of unknown image {}", annotation.image_id),
+            )
+        })?;
+
+        let [x, y, width, height] = annotation.bbox;
+        labels.entry(name.clone()).or_default().push(Rect {
+            x: x.max(0.0).round() as u32,
+            y: y.max(0.0).round() as u32,
+            width: width.max(0.0).round() as u32,
+            height: height.max(0.0).round() as u32,
+        });
+    }
+
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::metadata::frame_with_rects;
+    use super::super::scratch::Scratch;
+    use super::*;
+    use std::fs;
+
+    fn rects() -> Vec<Rect> {
+        vec![
+            Rect {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 20,
+            },
+            Rect {
+                x: 100,
+                y: 50,
+                width: 1,
+                height: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let scratch = Scratch::new();
+        let path = scratch.join("coco.json");
+        let frames = vec![
+            frame_with_rects("a.png", &rects()),
+            frame_with_rects("b.png", &[]),
+        ];
+
+        write(&frames, &path).unwrap();
+        let labels = read(&path).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["a.png"], rects());
+        assert!(labels["b.png"].is_empty());
+    }
+
+    #[test]
+    fn huge_boxes_are_clamped() {
+        let scratch = Scratch::new();
+        let path = scratch.join("coco.json");
+        fs::write(
+            &path,
+            r#"{
+                "images": [{ "id": 7, "file_name": "images/a.png" }],
+                "annotations": [{ "image_id": 7, "bbox": [1e20, -5, 1e20, 12.4] }]
+            }"#,
+        )
+        .unwrap();
+
+        let labels = read(&path).unwrap();
+        let label = labels["a.png"][0];
+        assert_eq!((label.x, label.y, label.height), (u32::MAX, 0, 12));
+        assert_eq!(label.intersection_over_union(&rects()[0]), 0.0);
+    }
+}
@@ -1,24 +1,229 @@
-use super::heat_map::CELL_SIZE;
-use super::image::DynamicImage;
+use super::canonical_size::{
+    canonical_size, expand_to_aspect_ratio, CanonicalSize, ResizeStrategy, Transform,
+};
+use super::grid::{Grid, Rect};
+use super::image::{
+    DynamicImage, FilterType, GenericImageView, GrayImage, ImageLuma8, ImageRgba8, Luma,
+};
 use super::visual_object::VisualObject;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Largest number of cells the rectangle around an object can be expanded by.
+pub const MAX_PADDING: u32 = 64;
+
+/// Largest number of cells the mask can be grown by. Each cell of the object
+/// marks a square of this many cells in each direction.
+pub const MAX_DILATE: u32 = 16;
+
+/// Largest radius in pixels of the blur which feathers the edge of the mask.
+pub const MAX_FEATHER: u32 = 64;
+
+/// How the highlights are cut out of the image.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cropping {
+    /// Number of cells the rectangle around the object is expanded by in each
+    /// direction. The expansion stops at the edges of the image.
+    pub padding: u32,
+
+    /// Makes pixels which do not belong to the object transparent.
+    pub mask: bool,
+
+    /// Grows the mask by given number of cells in each direction.
+    pub dilate: u32,
+
+    /// Blurs the edge of the mask over given number of pixels so that the
+    /// transparency fades in gradually.
+    pub feather: u32,
+
+    /// Also returns the binary mask of the object as a separate image.
+    pub write_mask: bool,
+
+    /// Resizes all highlights, and their masks, to the same size.
+    pub canonical_size: Option<CanonicalSize>,
+}
+
+impl Default for Cropping {
+    fn default() -> Self {
+        Self {
+            padding: 1,
+            mask: false,
+            dilate: 0,
+            feather: 0,
+            write_mask: false,
+            canonical_size: None,
+        }
+    }
+}
+
+impl Cropping {
+    /// Checks the values which would make the cropping fail or take too long.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.padding > MAX_PADDING {
+            return Err(format!("Padding cannot be larger than {}.", MAX_PADDING));
+        }
+
+        if self.dilate > MAX_DILATE {
+            return Err(format!("Dilation cannot be larger than {}.", MAX_DILATE));
+        }
+
+        if self.feather > MAX_FEATHER {
+            return Err(format!("Feathering cannot be larger than {}.", MAX_FEATHER));
+        }
+
+        if let Some(size) = &self.canonical_size {
+            if size.width == 0 || size.height == 0 {
+                return Err("Canonical size cannot be empty.".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Part of the original image with a single highlight.
+pub struct Crop {
+    pub image: DynamicImage,
+
+    /// White where the object is and black elsewhere. Only present if the
+    /// settings ask for it.
+    pub mask: Option<GrayImage>,
+
+    /// Where in the original image the crop comes from and how it was resized.
+    pub transform: Transform,
+}
 
 pub fn cut_highlights_from_image(
-    mut highlights: Vec<VisualObject>,
+    highlights: &mut [VisualObject],
     mut image: DynamicImage,
-) -> Vec<DynamicImage> {
+    grid: &Grid,
+    settings: &Cropping,
+) -> Vec<Crop> {
     highlights
         .iter_mut()
         .filter_map(|highlight| {
-            let (lower, higher) = highlight.size()?;
-            let lower = lower + highlight.reference;
-            let higher = higher + highlight.reference;
-
-            Some(image.crop(
-                (lower.x.max(1) - 1) * CELL_SIZE / 2,
-                (lower.y.max(1) - 1) * CELL_SIZE / 2,
-                (higher.x - lower.x + 2) * CELL_SIZE / 2,
-                (higher.y - lower.y + 2) * CELL_SIZE / 2,
-            ))
+            let bounds = highlight.bounds()?;
+            let mut rect = grid.bounds_to_pixels(bounds, settings.padding);
+
+            // Context around the highlight has to be taken before it is cut out.
+            if let Some(size) = &settings.canonical_size {
+                if size.strategy == ResizeStrategy::Context {
+                    rect = expand_to_aspect_ratio(rect, size, image.dimensions());
+                }
+            }
+
+            // The rectangle is within the image, therefore the crop is exactly
+            // of its size.
+            let crop = image.crop(rect.x, rect.y, rect.width, rect.height);
+
+            let mask = if settings.mask || settings.write_mask {
+                Some(object_mask(highlight, rect, grid, settings.dilate))
+            } else {
+                None
+            };
+
+            let crop = match (&mask, settings.mask) {
+                (Some(mask), true) => apply_mask(&crop, &feather(mask, settings.feather)),
+                _ => crop,
+            };
+
+            let mask = if settings.write_mask { mask } else { None };
+
+            let size = match &settings.canonical_size {
+                None => {
+                    return Some(Crop {
+                        image: crop,
+                        mask,
+                        transform: Transform::identity(rect),
+                    })
+                }
+                Some(size) => size,
+            };
+
+            // Masks are resized without interpolation so that they stay binary.
+            let (image, transform) = canonical_size(&crop, rect, size, size.filter.into());
+            let mask = mask.map(|mask| {
+                canonical_size(&ImageLuma8(mask), rect, size, FilterType::Nearest)
+                    .0
+                    .to_luma()
+            });
+
+            Some(Crop {
+                image,
+                mask,
+                transform,
+            })
         })
         .collect()
 }
+
+/// Renders the cells of the object, grown by given number of cells, into a mask
+/// of the part of the original image given by the rectangle.
+fn object_mask(highlight: &VisualObject, rect: Rect, grid: &Grid, dilate: u32) -> GrayImage {
+    let dilate = dilate as i64;
+    let mut cells: HashSet<(i64, i64)> = HashSet::new();
+    for point in highlight.points.iter() {
+        let point = *point + highlight.reference;
+
+        for dy in -dilate..=dilate {
+            for dx in -dilate..=dilate {
+                cells.insert((point.x as i64 + dx, point.y as i64 + dy));
+            }
+        }
+    }
+
+    GrayImage::from_fn(rect.width, rect.height, |x, y| {
+        let cell = grid.pixel_to_cell(rect.x + x, rect.y + y);
+
+        if cells.contains(&(cell.x as i64, cell.y as i64)) {
+            Luma([255])
+        } else {
+            Luma([0])
+        }
+    })
+}
+
+/// Smooths the mask with a box blur of given radius, first horizontally and then
+/// vertically.
+fn feather(mask: &GrayImage, radius: u32) -> GrayImage {
+    if radius == 0 {
+        return mask.clone();
+    }
+
+    let (width, height) = mask.dimensions();
+    let radius = radius as i64;
+    let blur = |source: &GrayImage, horizontal: bool| {
+        GrayImage::from_fn(width, height, |x, y| {
+            let (mut total, mut count) = (0_u32, 0_u32);
+
+            for offset in -radius..=radius {
+                let (sx, sy) = if horizontal {
+                    (x as i64 + offset, y as i64)
+                } else {
+                    (x as i64, y as i64 + offset)
+                };
+
+                if sx >= 0 && sy >= 0 && sx < width as i64 && sy < height as i64 {
+                    total += source.get_pixel(sx as u32, sy as u32).data[0] as u32;
+                    count += 1;
+                }
+            }
+
+            Luma([(total / count.max(1)) as u8])
+        })
+    };
+
+    blur(&blur(mask, true), false)
+}
+
+/// Uses the mask as the alpha channel of the image.
+fn apply_mask(image: &DynamicImage, mask: &GrayImage) -> DynamicImage {
+    let mut image = image.to_rgba();
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        pixel.data[3] = mask.get_pixel(x, y).data[0];
+    }
+
+    ImageRgba8(image)
+}
@@ -0,0 +1,490 @@
+use super::adaptive::Candidate;
+use super::cellular_automaton::cellular_automaton;
+use super::cut_highlights_from_image::{cut_highlights_from_image, Crop};
+use super::extract_highlights::extract_highlights;
+use super::find_edges::find_edges;
+use super::grid::Grid;
+use super::heat_map::heat_map;
+use super::image::{DynamicImage, GenericImageView, GrayImage};
+use super::integral_image::IntegralImage;
+use super::point::Point;
+use super::post_processing::{post_process, Decision};
+use super::pyramid::{PyramidMetadata, ScaleHighlight};
+use super::settings::Settings;
+use super::shape_descriptors::trace_contour;
+use super::visual_object::VisualObject;
+
+type GrayImageRaw = Vec<Vec<u32>>;
+
+/// Highlights found in a single image together with the intermediate results
+/// of the pipeline. Nothing is persisted yet.
+pub struct Detection {
+    pub width: u32,
+    pub height: u32,
+
+    /// Size of the cells the highlights were found with, which is the one in
+    /// the settings unless it is adaptive.
+    pub cell_size: u32,
+
+    /// Cell sizes the adaptive search tried, in order.
+    pub cell_size_search: Vec<Candidate>,
+
+    /// Highlights found at each cell size of the pyramid, if it was asked for.
+    pub pyramid: Option<PyramidMetadata>,
+
+    pub grid: Grid,
+
+    /// Edges found in the image, dark on white background.
+    pub edges: GrayImage,
+
+    /// Density of the edges in each cell and the maximum density.
+    pub heat_map: GrayImageRaw,
+    pub heat_max: u32,
+
+    /// Cells of the heat map the cellular automaton turned on.
+    pub automaton: Vec<Vec<bool>>,
+
+    pub highlights: Vec<VisualObject>,
+
+    /// Objects which were merged, suppressed or filtered out after extraction.
+    pub decisions: Vec<Decision>,
+
+    /// Highlights cut out of the image, in the same order as the highlights.
+    pub crops: Vec<Crop>,
+}
+
+/// Runs the whole pipeline on given image.
+pub fn detect(image: DynamicImage, settings: &Settings) -> Detection {
+    // Converts the image to grayscale and finds edges within the picture. Works
+    // only with bright images. Resulting image has white background with dark
+    // edges highlighted.
+    let edges = find_edges(&image);
+
+    // The edges are counted once, so that the heat map of each cell size the
+    // adaptive search tries is cheap to compute.
+    let integral = IntegralImage::new(&edges);
+
+    let (objects, cell_size_search) = match &settings.adaptive {
+        None => (
+            find_objects(&integral, settings.cell_size, settings),
+            Vec::new(),
+        ),
+        Some(adaptive) => adaptive.search(settings.cell_size, |cell_size| {
+            let objects = find_objects(&integral, cell_size, settings);
+            (objects.candidate(), objects)
+        }),
+    };
+    // Each level runs on the same edges, only the cells differ.
+    let pyramid = settings.pyramid.as_ref().map(|pyramid| {
+        let levels = pyramid
+            .levels()
+            .into_iter()
+            .map(|cell_size| {
+                find_objects(&integral, cell_size, settings).scale_highlights(image.dimensions())
+            })
+            .collect();
+        pyramid.link(levels)
+    });
+
+    let Objects {
+        cell_size,
+        heat_map,
+        heat_max,
+        automaton,
+        mut highlights,
+        decisions,
+    } = objects;
+
+    let (width, height) = image.dimensions();
+    let grid = Grid::new(cell_size, (width, height));
+    let crops = cut_highlights_from_image(&mut highlights, image, &grid, &settings.crop);
+
+    Detection {
+        width,
+        height,
+        cell_size,
+        cell_size_search,
+        pyramid,
+        grid,
+        edges,
+        heat_map,
+        heat_max,
+        automaton,
+        highlights,
+        decisions,
+        crops,
+    }
+}
+
+/// Highlights found with a single cell size, before they are cut out.
+struct Objects {
+    cell_size: u32,
+    heat_map: GrayImageRaw,
+    heat_max: u32,
+    automaton: Vec<Vec<bool>>,
+    highlights: Vec<VisualObject>,
+    decisions: Vec<Decision>,
+}
+
+fn find_objects(edges: &IntegralImage, cell_size: u32, settings: &Settings) -> Objects {
+    // From the bricked heat map creates more detailed one where each cell is half
+    // of the size of those in the bricked heat map. This multi-dimensional vector
+    // represents density of edges in the original image.
+    // Also returns maximum heat observed in the map and an average heat. This is
+    // used for calculating the rules of the cellular automaton.
+    let (heat_map, heat_max, heat_mean) = heat_map(edges, cell_size);
+
+    // Stabilizes each cell into one of two states. The original heat map is
+    // kept to score the objects later on.
+    let automaton = cellular_automaton(heat_map.clone(), heat_max, heat_mean);
+
+    // Finds objects using a recursive flood fill method. Objects which are too
+    // large are split with the strategy selected in the settings.
+    let mut highlights: Vec<VisualObject> = Vec::new();
+    extract_highlights(
+        automaton.clone(),
+        Point::new(0, 0),
+        settings,
+        &mut highlights,
+    );
+
+    for highlight in highlights.iter_mut() {
+        highlight.measure_saliency(&heat_map, heat_max);
+    }
+
+    // Merges, suppresses and filters the objects as configured in the settings.
+    let (highlights, decisions) = post_process(highlights, &settings.post_processing);
+
+    Objects {
+        cell_size,
+        heat_map,
+        heat_max,
+        automaton,
+        highlights,
+        decisions,
+    }
+}
+
+impl Objects {
+    /// Highlights of a level of the pyramid, not linked yet.
+    fn scale_highlights(mut self, dimensions: (u32, u32)) -> Vec<ScaleHighlight> {
+        let grid = Grid::new(self.cell_size, dimensions);
+        let cell_size = self.cell_size;
+
+        self.highlights
+            .iter_mut()
+            .filter_map(|highlight| {
+                let bounds = highlight.bounds()?;
+                Some(ScaleHighlight {
+                    cell_size,
+                    rect: grid.bounds_to_pixels(bounds, 0),
+                    bounds,
+                    cells: highlight.points.len(),
+                    saliency: highlight.saliency,
+                    parent: None,
+                    children: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    /// Number of highlights and the fraction of the cells they consist of.
+    fn candidate(&self) -> Candidate {
+        let cells: usize = self.heat_map.iter().map(|row| row.len()).sum();
+        let covered: usize = self
+            .highlights
+            .iter()
+            .map(|highlight| highlight.points.len())
+            .sum();
+
+        Candidate {
+            cell_size: self.cell_size,
+            highlights: self.highlights.len(),
+            coverage: covered as f64 / cells.max(1) as f64,
+        }
+    }
+}
+
+/// Corners of the outer contour of the object in pixels of the original image.
+pub fn contour(highlight: &mut VisualObject, grid: &Grid) -> Vec<Point> {
+    let corners = trace_contour(highlight).expect("Highlight is empty");
+
+    corners
+        .into_iter()
+        .map(|corner| grid.corner_to_pixel(corner))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::adaptive::{Adaptive, Band};
+    use super::super::grid::Rect;
+    use super::super::labels::Agreement;
+    use super::super::pyramid::Pyramid;
+    use super::super::synthetic::{generate, Scene, SceneSpec};
+    use super::*;
+
+    fn scene(seed: u64) -> Scene {
+        generate(&SceneSpec {
+            width: 320,
+            height: 180,
+            objects: 4,
+            seed,
+        })
+    }
+
+    fn settings(cell_size: u32) -> Settings {
+        Settings {
+            cell_size,
+            ..Settings::default()
+        }
+    }
+
+    fn rects(detection: &mut Detection) -> Vec<Rect> {
+        let grid = detection.grid;
+        detection
+            .highlights
+            .iter_mut()
+            .filter_map(|highlight| highlight.bounds())
+            .map(|bounds| grid.bounds_to_pixels(bounds, 0))
+            .collect()
+    }
+
+    /// Whether the rectangles overlap once the first one is expanded by given
+    /// number of pixels.
+    fn near(a: Rect, b: Rect, by: u32) -> bool {
+        b.x < a.x + a.width + by
+            && a.x < b.x + b.width + by
+            && b.y < a.y + a.height + by
+            && a.y < b.y + b.height + by
+    }
+
+    #[test]
+    fn edges_follow_outlines() {
+        for seed in 0..4 {
+            let scene = scene(seed);
+            let labels = scene.labels();
+            let (width, height) = (320, 180);
+            let detection = detect(scene.image, &settings(10));
+
+            let edges: Vec<Rect> = detection
+                .edges
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel.data[0] == 0)
+                .map(|(x, y, _)| Rect {
+                    x,
+                    y,
+                    width: 1,
+                    height: 1,
+                })
+                .collect();
+
+            // The filters mark the border of the image, but the flat background
+            // with its gentle gradient has no edges.
+            for edge in edges.iter() {
+                let border =
+                    edge.x < 2 || edge.y < 2 || edge.x + 2 >= width || edge.y + 2 >= height;
+                assert!(border || labels.iter().any(|label| near(*label, *edge, 3)));
+            }
+
+            for label in labels.iter() {
+                let perimeter = (2 * (label.width + label.height)) as usize;
+                let inside = edges.iter().filter(|edge| near(*label, **edge, 0));
+                assert!(inside.count() >= perimeter / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn automaton_covers_objects() {
+        for seed in 0..4 {
+            let scene = scene(seed);
+            let labels = scene.labels();
+            let detection = detect(scene.image, &settings(10));
+            let grid = detection.grid;
+
+            let mut cells: Vec<Rect> = Vec::new();
+            for (y, row) in detection.automaton.iter().enumerate() {
+                for (x, on) in row.iter().enumerate() {
+                    if *on {
+                        let cell = Point::new(x as u32, y as u32);
+                        cells.push(grid.bounds_to_pixels((cell, cell), 0));
+                    }
+                }
+            }
+
+            // Cells are only on around the objects, and each object has some.
+            let span = grid.cell_span();
+            for cell in cells.iter() {
+                assert!(labels.iter().any(|label| near(*label, *cell, span)));
+            }
+            for label in labels.iter() {
+                assert!(cells.iter().any(|cell| near(*label, *cell, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn finds_each_object() {
+        for seed in 0..8 {
+            let scene = scene(seed);
+            let labels = scene.labels();
+            let mut detection = detect(scene.image, &settings(6));
+
+            let agreement = Agreement::new(&rects(&mut detection), &labels, 0.5);
+            assert_eq!(agreement.recall(), 1.0, "seed {}", seed);
+            assert_eq!(agreement.precision(), 1.0, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn finds_each_object_with_coarse_cells() {
+        for seed in 0..8 {
+            let scene = scene(seed);
+            let labels = scene.labels();
+            let mut detection = detect(scene.image, &settings(10));
+
+            // Coarse cells give rougher rectangles.
+            let agreement = Agreement::new(&rects(&mut detection), &labels, 0.3);
+            assert_eq!(agreement.recall(), 1.0, "seed {}", seed);
+            assert_eq!(agreement.precision(), 1.0, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn empty_scene_has_no_highlights() {
+        let scene = generate(&SceneSpec {
+            width: 320,
+            height: 180,
+            objects: 0,
+            seed: 3,
+        });
+
+        let detection = detect(scene.image, &settings(10));
+        assert!(detection.highlights.is_empty());
+    }
+
+    #[test]
+    fn cells_larger_than_image_have_no_highlights() {
+        for cell_size in [200, 360, 512].iter() {
+            let detection = detect(scene(1).image, &settings(*cell_size));
+            assert!(detection.highlights.is_empty(), "cell size {}", cell_size);
+        }
+    }
+
+    #[test]
+    fn pipeline_is_deterministic() {
+        let mut a = detect(scene(5).image, &settings(10));
+        let mut b = detect(scene(5).image, &settings(10));
+
+        assert_eq!(rects(&mut a), rects(&mut b));
+        assert_eq!(a.heat_map, b.heat_map);
+        assert_eq!(a.automaton, b.automaton);
+        assert_eq!(a.edges.into_raw(), b.edges.into_raw());
+    }
+
+    fn adaptive(highlights: Band) -> Settings {
+        Settings {
+            cell_size: 14,
+            adaptive: Some(Adaptive {
+                highlights: Some(highlights),
+                ..Adaptive::default()
+            }),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn adaptive_cell_size_reaches_band() {
+        for seed in 0..3 {
+            let scene = generate(&SceneSpec {
+                width: 320,
+                height: 180,
+                objects: 8,
+                seed,
+            });
+            let band = Band { min: 8.0, max: 8.0 };
+            let detection = detect(scene.image, &adaptive(band));
+
+            // Coarse cells merge the objects, so the search walks to smaller
+            // ones until each object is found on its own.
+            let search = &detection.cell_size_search;
+            assert_eq!(detection.highlights.len(), 8, "seed {}", seed);
+            assert_eq!(search[0].cell_size, 14);
+            assert!(search
+                .windows(2)
+                .all(|w| w[1].cell_size + 2 == w[0].cell_size));
+            assert_eq!(search.last().unwrap().cell_size, detection.cell_size);
+            assert_eq!(detection.grid.cell_span() * 2, detection.cell_size);
+        }
+    }
+
+    #[test]
+    fn adaptive_search_stops_at_range() {
+        let band = Band {
+            min: 100.0,
+            max: 200.0,
+        };
+        let detection = detect(scene(2).image, &adaptive(band));
+
+        let tried: Vec<u32> = detection
+            .cell_size_search
+            .iter()
+            .map(|candidate| candidate.cell_size)
+            .collect();
+        assert_eq!(tried, vec![14, 12, 10, 8, 6]);
+
+        // None is within the band, the one with the most highlights is closest.
+        let most = detection
+            .cell_size_search
+            .iter()
+            .map(|candidate| candidate.highlights)
+            .max();
+        assert_eq!(Some(detection.highlights.len()), most);
+    }
+
+    #[test]
+    fn pyramid_links_objects_across_scales() {
+        let settings = Settings {
+            pyramid: Some(Pyramid {
+                cell_sizes: vec![6, 10, 14, 20],
+                ..Pyramid::default()
+            }),
+            ..Settings::default()
+        };
+
+        for seed in 0..4 {
+            let scene = scene(seed);
+            let labels = scene.labels();
+            let pyramid = detect(scene.image, &settings).pyramid.unwrap();
+            let highlights = &pyramid.highlights;
+            assert_eq!(pyramid.cell_sizes, vec![20, 14, 10, 6]);
+
+            for (index, highlight) in highlights.iter().enumerate() {
+                for child in highlight.children.iter() {
+                    assert_eq!(highlights[*child].parent, Some(index));
+                    assert!(highlights[*child].cell_size < highlight.cell_size);
+                }
+            }
+
+            // The finest level finds each object, and the flat list keeps only
+            // that one of each chain. Coarse highlights which group several
+            // objects stay in it too.
+            let leaves: Vec<Rect> = pyramid
+                .flat
+                .iter()
+                .map(|index| &highlights[*index])
+                .filter(|highlight| highlight.children.is_empty())
+                .map(|highlight| highlight.rect)
+                .collect();
+            let agreement = Agreement::new(&leaves, &labels, 0.5);
+            assert_eq!(agreement.recall(), 1.0, "seed {}", seed);
+            assert_eq!(agreement.precision(), 1.0, "seed {}", seed);
+
+            for index in pyramid.flat.iter() {
+                let highlight = &highlights[*index];
+                assert!(highlight.children.is_empty() || highlight.children.len() > 1);
+            }
+        }
+    }
+}
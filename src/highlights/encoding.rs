@@ -0,0 +1,193 @@
+use super::image::pnm::{PNMSubtype, SampleEncoding};
+use super::image::{DynamicImage, ImageError, ImageOutputFormat, ImageResult, ImageRgb8};
+use serde::{Deserialize, Serialize};
+
+/// Length of the PNG signature and the header chunk, which has to come first.
+const PNG_HEADER_LENGTH: usize = 8 + 4 + 4 + 13 + 4;
+
+/// In which format are the highlights persisted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Encoding {
+    pub format: OutputFormat,
+
+    /// Quality of JPEG images between 1 and 100.
+    pub jpeg_quality: u8,
+
+    /// Writes metadata of the highlight into the image file if the format
+    /// allows it. Only PNG does.
+    pub embed_metadata: bool,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Png,
+            jpeg_quality: 90,
+            embed_metadata: true,
+        }
+    }
+}
+
+/// Formats the image crate can encode. It cannot encode WebP.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Ppm,
+}
+
+impl OutputFormat {
+    /// Extension of the files in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Ppm => "ppm",
+        }
+    }
+}
+
+/// Encodes the image into bytes of given format. Text entries are embedded as
+/// PNG text chunks if the format is PNG and the settings allow it.
+pub fn encode(
+    image: &DynamicImage,
+    settings: &Encoding,
+    text: &[(&str, String)],
+) -> ImageResult<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+
+    match settings.format {
+        OutputFormat::Png => image.write_to(&mut bytes, ImageOutputFormat::PNG)?,
+        // Neither JPEG nor PPM have an alpha channel, masked highlights lose it.
+        OutputFormat::Jpeg => ImageRgb8(image.to_rgb())
+            .write_to(&mut bytes, ImageOutputFormat::JPEG(settings.jpeg_quality))?,
+        OutputFormat::Ppm => ImageRgb8(image.to_rgb()).write_to(
+            &mut bytes,
+            ImageOutputFormat::PNM(PNMSubtype::Pixmap(SampleEncoding::Binary)),
+        )?,
+        OutputFormat::Bmp => image.write_to(&mut bytes, ImageOutputFormat::BMP)?,
+    };
+
+    if settings.format == OutputFormat::Png && settings.embed_metadata {
+        let mut chunks: Vec<u8> = Vec::new();
+        for (keyword, value) in text {
+            chunks.extend(text_chunk(keyword, value).map_err(ImageError::FormatError)?);
+        }
+
+        // The text chunks can be anywhere after the header chunk.
+        bytes.splice(PNG_HEADER_LENGTH..PNG_HEADER_LENGTH, chunks);
+    }
+
+    Ok(bytes)
+}
+
+/// Builds a PNG text chunk. Values which Latin-1 can represent go into a tEXt
+/// chunk, others into an uncompressed iTXt chunk in UTF-8. The chunk ends with
+/// a checksum of its type and data.
+fn text_chunk(keyword: &str, value: &str) -> Result<Vec<u8>, String> {
+    // Keywords are 1 to 79 printable Latin-1 characters without leading or
+    // trailing spaces.
+    let keyword: Vec<u8> = latin1(keyword)
+        .filter(|bytes| (1..=79).contains(&bytes.len()))
+        .filter(|bytes| bytes.first() != Some(&b' ') && bytes.last() != Some(&b' '))
+        .filter(|bytes| bytes.iter().all(|b| (32..=126).contains(b) || *b >= 161))
+        .ok_or_else(|| format!("Invalid PNG text keyword {:?}", keyword))?;
+
+    if value.contains('\0') {
+        return Err(format!("PNG text {:?} contains a null byte", keyword));
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    match latin1(value) {
+        Some(value) => {
+            body.extend(b"tEXt");
+            body.extend(keyword);
+            body.push(0);
+            body.extend(value);
+        }
+        None => {
+            body.extend(b"iTXt");
+            body.extend(keyword);
+            // No compression, and empty language tag and translated keyword.
+            body.extend(&[0, 0, 0, 0, 0]);
+            body.extend(value.bytes());
+        }
+    }
+
+    let mut chunk: Vec<u8> = Vec::new();
+    chunk.extend(&((body.len() - 4) as u32).to_be_bytes());
+    chunk.extend(&body);
+    chunk.extend(&crc32(&body).to_be_bytes());
+
+    Ok(chunk)
+}
+
+/// Encodes the text in Latin-1 if it can be.
+fn latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| {
+            if (c as u32) < 256 {
+                Some(c as u8)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Checksum used by PNG chunks. The chunks are short, therefore the checksum is
+/// calculated bit by bit without a lookup table.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin1_values_are_text_chunks() {
+        let chunk = text_chunk("Frame", "clip/café.png").unwrap();
+        assert_eq!(&chunk[..4], &[0, 0, 0, 19]);
+        assert_eq!(&chunk[4..8], b"tEXt");
+        assert_eq!(&chunk[8..27], b"Frame\0clip/caf\xe9.png");
+        assert_eq!(&chunk[27..], &crc32(&chunk[4..27]).to_be_bytes());
+    }
+
+    #[test]
+    fn other_values_are_international_text_chunks() {
+        let chunk = text_chunk("Frame", "clip/猫.png").unwrap();
+        assert_eq!(&chunk[4..8], b"iTXt");
+        assert_eq!(&chunk[8..18], b"Frame\0\0\0\0\0");
+        assert_eq!(&chunk[18..(chunk.len() - 4)], "clip/猫.png".as_bytes());
+    }
+
+    #[test]
+    fn invalid_keywords_are_rejected() {
+        let long = "k".repeat(80);
+        for keyword in &["", " Frame", "Fra\0me", "猫", "Frame\n", long.as_str()] {
+            assert!(text_chunk(keyword, "value").is_err(), "{:?}", keyword);
+        }
+
+        assert!(text_chunk(&"k".repeat(79), "value").is_ok());
+        assert!(text_chunk("Frame", "clip\0.png").is_err());
+    }
+}
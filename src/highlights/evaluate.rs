@@ -0,0 +1,99 @@
+use super::detect::{detect, Detection};
+use super::grid::Rect;
+use super::image;
+use super::labels::{Agreement, Labels, Scores};
+use super::settings::Settings;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+
+/// How the highlights of the pipeline agree with the labelled boxes of a set
+/// of images.
+#[derive(Debug, Serialize)]
+pub struct EvaluationReport {
+    /// Parameters the pipeline ran with.
+    pub settings: Settings,
+
+    /// Highlights which overlap a labelled box at least this much match it.
+    pub iou_threshold: f32,
+
+    /// Scores of all frames together.
+    pub dataset: Scores,
+
+    /// Labelled frames, sorted by name.
+    pub frames: Vec<FrameEvaluation>,
+
+    /// Labelled frames which are not in the directory with the images.
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FrameEvaluation {
+    /// File name of the image.
+    pub frame: String,
+
+    pub highlights: Vec<Rect>,
+    pub labels: Vec<Rect>,
+    pub scores: Scores,
+}
+
+/// Runs the pipeline on each labelled image in given directory and matches its
+/// highlights with the labels by intersection over union.
+pub fn evaluate(
+    images_dir: &Path,
+    labels: &Labels,
+    settings: &Settings,
+    iou_threshold: f32,
+) -> io::Result<EvaluationReport> {
+    let (present, missing): (Vec<(&String, &Vec<Rect>)>, _) = labels
+        .iter()
+        .partition(|(frame, _)| images_dir.join(frame).is_file());
+
+    let results: Vec<io::Result<(FrameEvaluation, Agreement)>> = present
+        .par_iter()
+        .map(|(frame, labelled)| {
+            let image = image::open(images_dir.join(frame))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+            let Detection {
+                grid,
+                mut highlights,
+                ..
+            } = detect(image, settings);
+            let highlights: Vec<Rect> = highlights
+                .iter_mut()
+                .filter_map(|highlight| highlight.bounds())
+                .map(|bounds| grid.bounds_to_pixels(bounds, 0))
+                .collect();
+
+            let agreement = Agreement::new(&highlights, labelled, iou_threshold);
+            let evaluation = FrameEvaluation {
+                frame: frame.to_string(),
+                highlights,
+                labels: labelled.to_vec(),
+                scores: Scores::from(&agreement),
+            };
+            Ok((evaluation, agreement))
+        })
+        .collect();
+
+    let mut dataset = Agreement::default();
+    let mut frames: Vec<FrameEvaluation> = Vec::new();
+    for result in results {
+        let (evaluation, agreement) = result?;
+        dataset.add(&agreement);
+        frames.push(evaluation);
+    }
+
+    Ok(EvaluationReport {
+        settings: settings.clone(),
+        iou_threshold,
+        dataset: Scores::from(&dataset),
+        frames,
+        missing: missing
+            .into_iter()
+            .map(|(frame, _)| frame.to_string())
+            .collect(),
+    })
+}
@@ -0,0 +1,98 @@
+use super::coco;
+use super::metadata::read_frames;
+use super::npy::{self, SHARD_SIZE};
+use super::pyramid::PyramidMetadata;
+use super::voc;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the directory within the directory of a run the exports are written
+/// to.
+const EXPORT_DIR: &str = "export";
+
+/// Formats the highlights of a processed directory can be exported to.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "format", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ExportFormat {
+    /// Single JSON file with all frames.
+    Coco,
+    /// XML file per frame.
+    Voc,
+    /// NumPy arrays of the highlights split into shards of given number of
+    /// highlights.
+    Npy {
+        #[serde(default = "default_shard_size")]
+        shard_size: usize,
+    },
+    /// Single JSON file with the highlights of each level of the pyramid and
+    /// the links between them.
+    Pyramid,
+}
+
+/// Pyramid of a frame in the export.
+#[derive(Serialize)]
+struct PyramidFrame<'a> {
+    frame: &'a str,
+    width: u32,
+    height: u32,
+
+    #[serde(flatten)]
+    pyramid: &'a PyramidMetadata,
+}
+
+/// Converts the metadata of all processed frames in the directory of given run
+/// into annotations. Returns the path to the written file or directory.
+pub fn export(run_dir: &Path, format: ExportFormat) -> io::Result<PathBuf> {
+    let frames = read_frames(run_dir)?;
+    let export_dir = run_dir.join(EXPORT_DIR);
+    fs::create_dir_all(&export_dir)?;
+
+    // Name of the directory with the original images.
+    let folder = run_dir
+        .parent()
+        .and_then(|output_dir| output_dir.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    match format {
+        ExportFormat::Coco => {
+            let path = export_dir.join("coco.json");
+            coco::write(&frames, &path)?;
+            Ok(path)
+        }
+        ExportFormat::Voc => {
+            let path = export_dir.join("voc");
+            voc::write(&frames, folder, &path)?;
+            Ok(path)
+        }
+        ExportFormat::Npy { shard_size } => {
+            let path = export_dir.join("npy");
+            npy::write(&frames, run_dir, &path, shard_size)?;
+            Ok(path)
+        }
+        ExportFormat::Pyramid => {
+            // Frames processed without a pyramid have nothing to export.
+            let pyramids: Vec<PyramidFrame> = frames
+                .iter()
+                .filter_map(|frame| {
+                    frame.pyramid.as_ref().map(|pyramid| PyramidFrame {
+                        frame: &frame.frame,
+                        width: frame.width,
+                        height: frame.height,
+                        pyramid,
+                    })
+                })
+                .collect();
+
+            let path = export_dir.join("pyramid.json");
+            serde_json::to_writer(File::create(&path)?, &pyramids)?;
+            Ok(path)
+        }
+    }
+}
+
+fn default_shard_size() -> usize {
+    SHARD_SIZE
+}
@@ -1,41 +1,69 @@
 use super::helpers::pixel_value;
 use super::point::Point;
+use super::settings::{Settings, SplitStrategy};
 use super::visual_object::VisualObject;
+use super::watershed::split_by_watershed;
 
 type PointMap = Vec<Vec<bool>>;
 
+/// Objects which span this many cells or more in either direction are split.
+///
+/// This is the default, each request can choose its own limit.
 pub const MAX_CELLS: u32 = 40;
 
-pub fn extract_highlights(image: PointMap, reference: Point, objects: &mut Vec<VisualObject>) {
-    for mut highlight in find_highlights_in_map(image, reference) {
-        let size = highlight.size();
-
-        if size.is_none() {
-            continue;
-        }
-
-        let (lower, higher) = size.unwrap();
+/// Extracts objects from given point map. Objects that are larger than the
+/// limit are split into smaller ones with the strategy from settings.
+pub fn extract_highlights(
+    image: PointMap,
+    reference: Point,
+    settings: &Settings,
+    objects: &mut Vec<VisualObject>,
+) {
+    for highlight in find_highlights_in_map(image, reference) {
+        keep_or_split(highlight, settings, objects);
+    }
+}
 
-        if lower.x == higher.x || lower.y == higher.y {
-            continue;
-        }
+/// Pushes the highlight to the objects if it fits the limit. Otherwise divides
+/// it and tries again with each of its parts.
+fn keep_or_split(
+    mut highlight: VisualObject,
+    settings: &Settings,
+    objects: &mut Vec<VisualObject>,
+) {
+    let (lower, higher) = match highlight.size() {
+        None => return,
+        Some(size) => size,
+    };
+
+    if higher.x - lower.x < settings.max_cells && higher.y - lower.y < settings.max_cells {
+        objects.push(highlight);
+        return;
+    }
 
-        if higher.x - lower.x < MAX_CELLS && higher.y - lower.y < MAX_CELLS {
-            objects.push(highlight);
-            continue;
+    match settings.split {
+        SplitStrategy::Peel => {
+            if let Some(map) = highlight.peeled_map() {
+                extract_highlights(map, highlight.reference + lower, settings, objects);
+            }
         }
-
-        match highlight.peeled_map() {
-            None => continue,
-            Some(map) => extract_highlights(map, highlight.reference + lower, objects),
-        };
+        SplitStrategy::Watershed => match split_by_watershed(&mut highlight) {
+            // The object has no neck to split along, so it is kept whole rather
+            // than losing its cells.
+            None => objects.push(highlight),
+            Some(children) => {
+                for child in children {
+                    keep_or_split(child, settings, objects);
+                }
+            }
+        },
     }
 }
 
 /// Finds objects within given image heatmap. Uses flood fill algorithm which,
 /// after finding any highlighted unvisited point within the image, selects all
-/// highlighted other points in the neighbourhood. This happens recursively for
-/// each highlighted unvisited point.
+/// highlighted other points in the neighbourhood, and then the points in their
+/// neighbourhood until there are none left.
 fn find_highlights_in_map(mut image: PointMap, reference: Point) -> Vec<VisualObject> {
     // Currently iterated point in the image.
     let mut current_point: Point = Point::new(0, 0);
@@ -43,7 +71,7 @@ fn find_highlights_in_map(mut image: PointMap, reference: Point) -> Vec<VisualOb
     let mut objects: Vec<VisualObject> = Vec::new();
 
     // Should the image be empty, return empty vector.
-    if image.len() == 0 || image[0].len() == 0 {
+    if image.is_empty() || image[0].is_empty() {
         return objects;
     }
 
@@ -78,28 +106,82 @@ fn find_highlights_in_map(mut image: PointMap, reference: Point) -> Vec<VisualOb
     objects
 }
 
-/// Recursively finds a single object within given image. It calls this function
-/// for every new highlighted point.
+/// Finds a single object within given image, starting at one of its
+/// highlighted points. Visits the points in the same order as a recursion over
+/// the Moore neighbourhood would, but with an explicit stack, so that large
+/// objects cannot overflow the stack of the thread.
 fn flood_fill(point: Point, object: &mut VisualObject, image: &mut PointMap) {
-    // Adds currently iterated point to the object and set that point to no
-    // highlighted.
-    object.push(point);
-    image[point.y as usize][point.x as usize] = false;
-
-    // Iterates over the Moore neighbourhood of currently iterated point.
-    for y in (point.y as isize - 1)..(point.y as isize + 2) {
-        if y < 0 {
+    // Adds the point to the object and sets it to not highlighted.
+    fn visit(point: Point, object: &mut VisualObject, image: &mut PointMap) {
+        object.push(point);
+        image[point.y as usize][point.x as usize] = false;
+    }
+
+    visit(point, object, image);
+    // Each point on the stack has the index of the next point of its Moore
+    // neighbourhood to visit, row by row.
+    let mut stack: Vec<(Point, isize)> = vec![(point, 0)];
+    while let Some((point, neighbour)) = stack.last_mut() {
+        if *neighbour == 9 {
+            stack.pop();
             continue;
         }
 
-        for x in (point.x as isize - 1)..(point.x as isize + 2) {
-            // If the Moore's point is not highlighted, skips.
-            if x < 0 || !pixel_value(image, x, y, false) {
-                continue;
-            }
+        let x = point.x as isize + *neighbour % 3 - 1;
+        let y = point.y as isize + *neighbour / 3 - 1;
+        *neighbour += 1;
+
+        // If the Moore's point is not highlighted, skips.
+        if x < 0 || y < 0 || !pixel_value(image, x, y, false) {
+            continue;
+        }
+
+        let next = Point::new(x as u32, y as u32);
+        visit(next, object, image);
+        stack.push((next, 0));
+    }
+}
 
-            // Visits the Moore's point.
-            flood_fill(Point::new(x as u32, y as u32), object, image);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flood_fill_finds_diagonal_neighbours() {
+        let mut map: PointMap = vec![vec![false; 6]; 4];
+        for (x, y) in &[(0, 0), (1, 1), (2, 2), (4, 0), (5, 1)] {
+            map[*y][*x] = true;
         }
+
+        let objects = find_highlights_in_map(map, Point::new(0, 0));
+        let points: Vec<Vec<Point>> = objects.into_iter().map(|o| o.points).collect();
+        assert_eq!(
+            points,
+            vec![
+                vec![Point::new(0, 0), Point::new(1, 1), Point::new(2, 2)],
+                vec![Point::new(4, 0), Point::new(5, 1)],
+            ]
+        );
+    }
+
+    #[test]
+    fn flood_fill_does_not_overflow_stack() {
+        // A snake through the whole map is a path a recursion would follow
+        // point by point.
+        let size = 1000;
+        let mut map: PointMap = vec![vec![false; size]; size];
+        for (y, row) in map.iter_mut().enumerate() {
+            if y % 2 == 0 {
+                row.iter_mut().for_each(|point| *point = true);
+            } else if y % 4 == 1 {
+                row[size - 1] = true;
+            } else {
+                row[0] = true;
+            }
+        }
+
+        let objects = find_highlights_in_map(map, Point::new(0, 0));
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].points.len(), size * size / 2 + size / 2);
     }
 }
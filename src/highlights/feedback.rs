@@ -0,0 +1,176 @@
+use super::grid::Rect;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the log in the directory of a run.
+const FEEDBACK_LOG: &str = "feedback.jsonl";
+
+/// Judgement of a highlight by a person.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Feedback {
+    pub verdict: Verdict,
+
+    /// Rectangle in pixels of the original image the highlight should have
+    /// had, if it was off.
+    #[serde(default)]
+    pub rect: Option<Rect>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Accept,
+    Reject,
+}
+
+/// Line of the feedback log.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FeedbackEntry {
+    /// File name of the original image without extension.
+    pub frame: String,
+
+    /// Index of the highlight in the metadata of the frame.
+    pub index: usize,
+
+    #[serde(flatten)]
+    pub feedback: Feedback,
+
+    /// Unix time in seconds when the feedback was given.
+    pub timestamp: u64,
+}
+
+impl FeedbackEntry {
+    pub fn new(frame: &str, index: usize, feedback: Feedback) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            frame: frame.to_string(),
+            index,
+            feedback,
+            timestamp,
+        }
+    }
+}
+
+/// Appends the entry to the log in the directory of given run. The log is never
+/// rewritten, so later entries about the same highlight supersede earlier ones.
+pub fn append_feedback(output_dir: &Path, entry: &FeedbackEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    // The line is written at once so that concurrent appends do not interleave.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_dir.join(FEEDBACK_LOG))?
+        .write_all(&line)
+}
+
+/// Whether anyone gave feedback on the highlights of given run.
+pub fn has_feedback(output_dir: &Path) -> bool {
+    output_dir.join(FEEDBACK_LOG).is_file()
+}
+
+/// Reads all entries about given highlight from the log, oldest first. Lines
+/// which cannot be parsed, e.g. of an append which was torn, are logged and
+/// skipped.
+pub fn read_feedback(
+    output_dir: &Path,
+    frame: &str,
+    index: usize,
+) -> io::Result<Vec<FeedbackEntry>> {
+    let path = output_dir.join(FEEDBACK_LOG);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries: Vec<FeedbackEntry> = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: FeedbackEntry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(error) => {
+                println!("Skipping feedback in {:?}: {}.", output_dir, error);
+                continue;
+            }
+        };
+        if entry.frame == frame && entry.index == index {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scratch::Scratch;
+    use super::*;
+
+    fn feedback(verdict: Verdict) -> Feedback {
+        Feedback {
+            verdict,
+            rect: None,
+        }
+    }
+
+    #[test]
+    fn entries_of_highlight_oldest_first() {
+        let scratch = Scratch::new();
+        assert!(!has_feedback(&scratch));
+        assert!(read_feedback(&scratch, "1", 0).unwrap().is_empty());
+
+        for (frame, index, verdict) in &[
+            ("1", 0, Verdict::Accept),
+            ("1", 1, Verdict::Accept),
+            ("2", 0, Verdict::Accept),
+            ("1", 0, Verdict::Reject),
+        ] {
+            let entry = FeedbackEntry::new(frame, *index, feedback(*verdict));
+            append_feedback(&scratch, &entry).unwrap();
+        }
+
+        assert!(has_feedback(&scratch));
+        let verdicts: Vec<Verdict> = read_feedback(&scratch, "1", 0)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.feedback.verdict)
+            .collect();
+        assert_eq!(verdicts, vec![Verdict::Accept, Verdict::Reject]);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let scratch = Scratch::new();
+        let entry = FeedbackEntry::new("1", 0, feedback(Verdict::Accept));
+        append_feedback(&scratch, &entry).unwrap();
+
+        // An append which was torn in the middle of the line.
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(scratch.join(FEEDBACK_LOG))
+            .unwrap();
+        log.write_all(b"\n{\"frame\":\"1\",\"ind\n").unwrap();
+
+        let entry = FeedbackEntry::new("1", 0, feedback(Verdict::Reject));
+        append_feedback(&scratch, &entry).unwrap();
+
+        let verdicts: Vec<Verdict> = read_feedback(&scratch, "1", 0)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.feedback.verdict)
+            .collect();
+        assert_eq!(verdicts, vec![Verdict::Accept, Verdict::Reject]);
+    }
+}
@@ -43,7 +43,7 @@ const FILTERS: [[f32; 9]; 5] = [
 /// Finds edges in given grayscale picture by using two 3x3 matrixes. First one
 /// detects horizontal edges, the second one vertical.
 pub fn find_edges(image: &DynamicImage) -> GrayImage {
-    let image = smooth_out_polarized_pixels(&image);
+    let image = smooth_out_polarized_pixels(image);
 
     let mut detectors: Vec<GrayImage> = Vec::new();
     for matrix in FILTERS.iter() {
@@ -0,0 +1,263 @@
+use super::point::Point;
+use serde::{Deserialize, Serialize};
+
+/// Rectangle in pixels of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+
+    /// Area of the overlap of both rectangles relative to the area of their
+    /// union, between 0 and 1.
+    pub fn intersection_over_union(&self, other: &Rect) -> f32 {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        // Labels read from files can reach past the largest coordinate.
+        let right = self
+            .x
+            .saturating_add(self.width)
+            .min(other.x.saturating_add(other.width));
+        let bottom = self
+            .y
+            .saturating_add(self.height)
+            .min(other.y.saturating_add(other.height));
+        if right <= left || bottom <= top {
+            return 0.0;
+        }
+
+        // The areas of two huge rectangles do not fit into an integer together.
+        let intersection = (u64::from(right - left) * u64::from(bottom - top)) as f64;
+        let union = self.area() as f64 + other.area() as f64 - intersection;
+        (intersection / union) as f32
+    }
+}
+
+/// Maps cells of the heat map to pixels of the image the heat map was computed
+/// from and back.
+///
+/// Each cell of the heat map is an average of four overlaying cells of the
+/// bricked heat map, therefore it is centred on a square of `cell_size / 2`
+/// pixels. The cell at `x;y` covers pixels from `x * cell_size / 2` up to, but
+/// not including, `(x + 1) * cell_size / 2`. The same goes for the y axis.
+#[derive(Clone, Copy, Debug)]
+pub struct Grid {
+    cell_size: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Grid {
+    /// Creates the mapping for an image of given dimensions in pixels.
+    pub fn new(cell_size: u32, (width, height): (u32, u32)) -> Self {
+        Self {
+            cell_size,
+            width,
+            height,
+        }
+    }
+
+    /// Number of pixels a cell of the heat map spans in each direction.
+    pub fn cell_span(&self) -> u32 {
+        self.cell_size / 2
+    }
+
+    /// Cell of the heat map which covers given pixel.
+    pub fn pixel_to_cell(&self, x: u32, y: u32) -> Point {
+        Point::new(x / self.cell_span(), y / self.cell_span())
+    }
+
+    /// Pixels covered by the cells within given bounds, expanded by the padding
+    /// of cells in each direction. The rectangle is clamped to the image, so the
+    /// padding is cut off near its edges.
+    pub fn bounds_to_pixels(&self, (lower, higher): (Point, Point), padding: u32) -> Rect {
+        let span = self.cell_span();
+        let x = lower.x.saturating_sub(padding).saturating_mul(span);
+        let y = lower.y.saturating_sub(padding).saturating_mul(span);
+        let right = higher.x.saturating_add(1).saturating_add(padding);
+        let bottom = higher.y.saturating_add(1).saturating_add(padding);
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        let right = right.saturating_mul(span).min(self.width).max(x);
+        let bottom = bottom.saturating_mul(span).min(self.height).max(y);
+
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+
+    /// Pixel at given corner of the cells, e.g. a corner of the contour of an
+    /// object. The corner `x;y` is the top left corner of the cell `x;y`.
+    pub fn corner_to_pixel(&self, corner: Point) -> Point {
+        let span = self.cell_span();
+
+        Point::new(
+            (corner.x * span).min(self.width),
+            (corner.y * span).min(self.height),
+        )
+    }
+
+    /// Smallest bounds of cells which cover all pixels in given rectangle.
+    #[cfg(test)]
+    pub fn pixels_to_bounds(&self, rect: Rect) -> (Point, Point) {
+        let span = self.cell_span();
+        let right = (rect.x + rect.width).max(rect.x + 1);
+        let bottom = (rect.y + rect.height).max(rect.y + 1);
+
+        (
+            self.pixel_to_cell(rect.x, rect.y),
+            Point::new((right - 1) / span, (bottom - 1) / span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Grid {
+        Grid::new(10, (640, 360))
+    }
+
+    #[test]
+    fn pixel_round_trip() {
+        let grid = grid();
+
+        for (x, y) in [(0, 0), (4, 4), (5, 5), (333, 121), (639, 359)].iter() {
+            let cell = grid.pixel_to_cell(*x, *y);
+            let rect = grid.bounds_to_pixels((cell, cell), 0);
+
+            assert!(rect.x <= *x && *x < rect.x + rect.width);
+            assert!(rect.y <= *y && *y < rect.y + rect.height);
+            assert_eq!(rect.width, grid.cell_span());
+            assert_eq!(rect.height, grid.cell_span());
+        }
+    }
+
+    #[test]
+    fn bounds_round_trip() {
+        let grid = grid();
+        let bounds = (Point::new(3, 7), Point::new(20, 9));
+
+        let rect = grid.bounds_to_pixels(bounds, 0);
+        assert_eq!(
+            rect,
+            Rect {
+                x: 15,
+                y: 35,
+                width: 90,
+                height: 15,
+            }
+        );
+        assert_eq!(grid.pixels_to_bounds(rect), bounds);
+    }
+
+    #[test]
+    fn padding_is_symmetric() {
+        let grid = grid();
+        let bounds = (Point::new(10, 10), Point::new(12, 11));
+
+        let tight = grid.bounds_to_pixels(bounds, 0);
+        let padded = grid.bounds_to_pixels(bounds, 2);
+
+        assert_eq!(tight.x - padded.x, 10);
+        assert_eq!(tight.y - padded.y, 10);
+        assert_eq!(
+            (padded.x + padded.width) - (tight.x + tight.width),
+            tight.x - padded.x
+        );
+        assert_eq!(
+            (padded.y + padded.height) - (tight.y + tight.height),
+            tight.y - padded.y
+        );
+    }
+
+    #[test]
+    fn padding_is_clamped_to_image() {
+        let grid = grid();
+
+        let top_left = grid.bounds_to_pixels((Point::new(0, 0), Point::new(1, 1)), 1);
+        assert_eq!(
+            top_left,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 15,
+                height: 15,
+            }
+        );
+
+        let bottom_right = grid.bounds_to_pixels((Point::new(126, 70), Point::new(127, 71)), 1);
+        assert_eq!(
+            bottom_right,
+            Rect {
+                x: 625,
+                y: 345,
+                width: 15,
+                height: 15,
+            }
+        );
+    }
+
+    #[test]
+    fn huge_padding_is_clamped_to_image() {
+        let grid = grid();
+
+        let rect = grid.bounds_to_pixels((Point::new(10, 10), Point::new(12, 11)), u32::MAX);
+        assert_eq!(
+            rect,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 360,
+            }
+        );
+    }
+
+    #[test]
+    fn padding_is_clamped_to_uneven_image() {
+        // The heat map has 73 columns which span 365 of 367 pixels.
+        let grid = Grid::new(10, (367, 100));
+
+        let rect = grid.bounds_to_pixels((Point::new(71, 0), Point::new(72, 0)), 1);
+        assert_eq!(rect.x, 350);
+        assert_eq!(rect.x + rect.width, 367);
+    }
+
+    #[test]
+    fn intersection_over_union() {
+        let rect = Rect {
+            x: 10,
+            y: 10,
+            width: 20,
+            height: 10,
+        };
+        let shifted = Rect { x: 20, ..rect };
+        let apart = Rect { x: 30, ..rect };
+
+        assert_eq!(rect.intersection_over_union(&rect), 1.0);
+        assert_eq!(rect.intersection_over_union(&shifted), 1.0 / 3.0);
+        assert_eq!(shifted.intersection_over_union(&rect), 1.0 / 3.0);
+        assert_eq!(rect.intersection_over_union(&apart), 0.0);
+
+        let huge = Rect {
+            x: u32::MAX - 5,
+            y: 0,
+            width: u32::MAX,
+            height: u32::MAX,
+        };
+        assert_eq!(huge.intersection_over_union(&rect), 0.0);
+        assert!(huge.intersection_over_union(&huge) > 0.0);
+    }
+}
@@ -1,6 +1,15 @@
+use std::path::Path;
+
+/// Extensions of the files which can be opened as images, the same ones
+/// `image::open` recognises.
+const IMAGE_EXTENSIONS: [&str; 14] = [
+    "jpg", "jpeg", "png", "gif", "webp", "tif", "tiff", "tga", "bmp", "ico", "hdr", "pbm", "pam",
+    "ppm",
+];
+
 /// Helper function for accessing values at given address in vector. If the
 /// address is out of bounds, it delivers the default value instead.
-pub fn pixel_value<T: Copy>(vec: &Vec<Vec<T>>, x: isize, y: isize, default: T) -> T {
+pub fn pixel_value<T: Copy>(vec: &[Vec<T>], x: isize, y: isize, default: T) -> T {
     if x < 0 || y < 0 {
         return default;
     }
@@ -23,3 +32,44 @@ pub fn to_point_map(input: Vec<Vec<u32>>) -> Vec<Vec<bool>> {
         .map(|row| row.iter().map(|point| *point != 0).collect())
         .collect()
 }
+
+/// Names of the directories can only contain alphanumeric characters and
+/// underscores, so that they cannot point outside of the shared volume. An
+/// empty name would point to the shared volume itself.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| char::is_alphanumeric(c) || c == '_')
+}
+
+/// Whether the file is an image which can be processed as a frame. Hidden
+/// files and files of other types, such as logs of the tool which extracted
+/// the frames, are not frames.
+pub fn is_image(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    !name.starts_with('.')
+        && path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| {
+                IMAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for valid in &["clip", "clip_01", "Ünïcode"] {
+            assert!(is_valid_name(valid), "{}", valid);
+        }
+
+        for invalid in &["", ".", "..", "a/b", "a.b", "a-b", " "] {
+            assert!(!is_valid_name(invalid), "{}", invalid);
+        }
+    }
+}
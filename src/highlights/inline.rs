@@ -0,0 +1,210 @@
+use super::adaptive::Candidate;
+use super::detect::{contour, detect, Detection};
+use super::encoding::{encode, Encoding};
+use super::grid::Rect;
+use super::image::{
+    self, GrayImage, ImageDecoder, ImageError, ImageFormat, ImageLuma8, ImageResult, Luma,
+};
+use super::point::Point;
+use super::post_processing::Decision;
+use super::pyramid::PyramidMetadata;
+use super::settings::Settings;
+use serde::Serialize;
+use std::io::Cursor;
+
+/// Highlights found in an image which was sent directly in the request.
+#[derive(Serialize)]
+pub struct InlineHighlights {
+    pub width: u32,
+    pub height: u32,
+    pub cell_size: u32,
+
+    /// Cell sizes the adaptive search tried, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cell_size_search: Vec<Candidate>,
+
+    pub highlights: Vec<InlineHighlight>,
+
+    /// Objects which were merged, suppressed or filtered out after extraction.
+    pub post_processing: Vec<Decision>,
+
+    /// Highlights found at each cell size of the pyramid with the links
+    /// between them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pyramid: Option<PyramidMetadata>,
+
+    /// Intermediate results of the pipeline, if they were asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugImages>,
+}
+
+#[derive(Serialize)]
+pub struct InlineHighlight {
+    /// Pixels of the image covered by the object.
+    pub rect: Rect,
+
+    /// Smallest rectangle around the object in cells of the heat map.
+    pub bounds: (Point, Point),
+
+    /// Corners of the outer contour of the object in pixels, clockwise.
+    pub contour: Vec<Point>,
+
+    /// Number of cells the object consists of.
+    pub cells: usize,
+
+    /// Saliency of the object between 0 and 1.
+    pub score: f32,
+
+    /// The highlight in the format from the settings encoded as base64, if the
+    /// crops were asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<String>,
+}
+
+/// Stages of the pipeline as PNG images encoded as base64.
+#[derive(Serialize)]
+pub struct DebugImages {
+    /// Edges found in the image, dark on white background.
+    pub edges: String,
+
+    /// Heat of each cell scaled so that the hottest cell is white.
+    pub heat_map: String,
+
+    /// Cells turned on by the cellular automaton are white.
+    pub automaton: String,
+}
+
+/// Width and height of the image given by its encoded bytes. Only the header
+/// is read, so that an image too large to process is not decoded.
+pub fn image_dimensions(bytes: &[u8]) -> ImageResult<(u64, u64)> {
+    let reader = Cursor::new(bytes);
+    Ok(match image::guess_format(bytes)? {
+        ImageFormat::PNG => image::png::PNGDecoder::new(reader)?.dimensions(),
+        ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(reader)?.dimensions(),
+        ImageFormat::GIF => image::gif::Decoder::new(reader)?.dimensions(),
+        ImageFormat::WEBP => image::webp::WebpDecoder::new(reader)?.dimensions(),
+        ImageFormat::TIFF => image::tiff::TIFFDecoder::new(reader)?.dimensions(),
+        ImageFormat::BMP => image::bmp::BMPDecoder::new(reader)?.dimensions(),
+        ImageFormat::ICO => image::ico::ICODecoder::new(reader)?.dimensions(),
+        ImageFormat::HDR => image::hdr::HDRAdapter::new(reader)?.dimensions(),
+        ImageFormat::PNM => image::pnm::PNMDecoder::new(reader)?.dimensions(),
+        format => {
+            return Err(ImageError::UnsupportedError(format!(
+                "Format {:?} is not supported.",
+                format
+            )))
+        }
+    })
+}
+
+/// Finds the highlights in the image given by its encoded bytes. Nothing is
+/// persisted, the results are returned instead.
+pub fn identify_inline(
+    bytes: &[u8],
+    settings: &Settings,
+    crops: bool,
+    debug: bool,
+) -> ImageResult<InlineHighlights> {
+    let image = image::load_from_memory(bytes)?;
+    let Detection {
+        width,
+        height,
+        cell_size,
+        cell_size_search,
+        pyramid,
+        grid,
+        edges,
+        heat_map,
+        heat_max,
+        automaton,
+        mut highlights,
+        decisions,
+        crops: images,
+    } = detect(image, settings);
+
+    let mut inline: Vec<InlineHighlight> = Vec::new();
+    let with_bounds = highlights
+        .iter_mut()
+        .zip(images.iter())
+        .filter_map(|(highlight, crop)| Some((highlight.bounds()?, highlight, crop)));
+    for (bounds, highlight, crop) in with_bounds {
+        let crop = if crops {
+            Some(base64::encode(&encode(
+                &crop.image,
+                &settings.encoding,
+                &[],
+            )?))
+        } else {
+            None
+        };
+
+        inline.push(InlineHighlight {
+            rect: grid.bounds_to_pixels(bounds, 0),
+            bounds,
+            contour: contour(highlight, &grid),
+            cells: highlight.points.len(),
+            score: highlight.saliency,
+            crop,
+        });
+    }
+
+    let debug = if debug {
+        let heat = GrayImage::from_fn(columns(&heat_map), heat_map.len() as u32, |x, y| {
+            Luma([(heat_map[y as usize][x as usize] * 255 / heat_max.max(1)) as u8])
+        });
+        let on = GrayImage::from_fn(columns(&automaton), automaton.len() as u32, |x, y| {
+            Luma([if automaton[y as usize][x as usize] {
+                255
+            } else {
+                0
+            }])
+        });
+
+        Some(DebugImages {
+            edges: png(edges)?,
+            heat_map: png(heat)?,
+            automaton: png(on)?,
+        })
+    } else {
+        None
+    };
+
+    Ok(InlineHighlights {
+        width,
+        height,
+        cell_size,
+        cell_size_search,
+        highlights: inline,
+        post_processing: decisions,
+        pyramid,
+        debug,
+    })
+}
+
+/// Width of a map stored as rows.
+fn columns<T>(map: &[Vec<T>]) -> u32 {
+    map.first().map(|row| row.len()).unwrap_or(0) as u32
+}
+
+/// Encodes a grayscale image as PNG in base64.
+fn png(image: GrayImage) -> ImageResult<String> {
+    let bytes = encode(&ImageLuma8(image), &Encoding::default(), &[])?;
+
+    Ok(base64::encode(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions_from_header() {
+        let mut png: Vec<u8> = Vec::new();
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[255; 30 * 20], 30, 20, image::Gray(8))
+            .unwrap();
+
+        assert_eq!(image_dimensions(&png).unwrap(), (30, 20));
+        assert!(image_dimensions(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use super::image::GrayImage;
+
+/// Number of edge pixels in every rectangle of the edge image in constant
+/// time. It is computed once per frame, so that heat maps of any cell size can
+/// be derived from it without counting the pixels again.
+pub struct IntegralImage {
+    width: u32,
+    height: u32,
+
+    /// Number of edge pixels above and to the left of each position, with an
+    /// extra row and column of zeros at the start.
+    sums: Vec<u32>,
+}
+
+impl IntegralImage {
+    /// Counts the edge pixels, which are black, of given image.
+    pub fn new(edges: &GrayImage) -> Self {
+        let (width, height) = edges.dimensions();
+        let stride = width as usize + 1;
+        let mut sums = vec![0; stride * (height as usize + 1)];
+
+        for y in 0..height as usize {
+            let mut row = 0;
+            for x in 0..width as usize {
+                if edges.get_pixel(x as u32, y as u32).data[0] == 0 {
+                    row += 1;
+                }
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Number of edge pixels in the rectangle which has to fit the image.
+    pub fn count(&self, x: u32, y: u32, width: u32, height: u32) -> u32 {
+        let stride = self.width as usize + 1;
+        let (left, top) = (x as usize, y as usize);
+        let (right, bottom) = (left + width as usize, top + height as usize);
+
+        self.sums[bottom * stride + right] + self.sums[top * stride + left]
+            - self.sums[top * stride + right]
+            - self.sums[bottom * stride + left]
+    }
+}
@@ -0,0 +1,177 @@
+use super::settings::Settings;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// Name of the record of the job in the directory of its run.
+pub const JOB_RECORD: &str = "run.json";
+
+/// Everything needed to process the request again after a restart, including
+/// all parameters of the pipeline. It is persisted before any frame is sent to
+/// the worker.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobRecord {
+    /// Name of the directory with the images.
+    pub name: String,
+
+    /// Identifier of the run, which is also the name of its directory.
+    pub run: String,
+
+    /// When the run was created, in seconds since the Unix epoch.
+    pub created: u64,
+
+    pub settings: Settings,
+
+    /// File names of the images in the directory at the time of the request.
+    pub frames: Vec<String>,
+}
+
+/// Persists the record into the directory of its run.
+pub fn record_job(output_dir: &Path, record: &JobRecord) -> io::Result<()> {
+    let path = output_dir.join(JOB_RECORD);
+    let temporary = output_dir.join(format!("{}.tmp", JOB_RECORD));
+
+    // The record is renamed into place so that it is never read half written.
+    serde_json::to_writer_pretty(File::create(&temporary)?, record)?;
+    fs::rename(temporary, path)
+}
+
+/// Directory in the directory of a run with a marker of each complete frame.
+const DONE_DIR: &str = ".done";
+
+/// Directory in the directory of a run with a marker of each frame which could
+/// not be processed. The marker holds the reason.
+const FAILED_DIR: &str = ".failed";
+
+/// Reads the record of the job in the directory of given run, if there is one.
+pub fn read_job(output_dir: &Path) -> io::Result<Option<JobRecord>> {
+    let path = output_dir.join(JOB_RECORD);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_reader(File::open(path)?)?))
+}
+
+/// Reads the records of the jobs of all runs of all directories in the output
+/// root, sorted by the name of the directory and the run. Directories and
+/// records which cannot be read are logged and skipped, so that one of them
+/// does not keep the others from being resumed.
+pub fn read_jobs(output_root: &Path) -> Vec<JobRecord> {
+    let mut records: Vec<JobRecord> = Vec::new();
+
+    let entries = match fs::read_dir(output_root) {
+        Ok(entries) => entries,
+        Err(error) => {
+            println!("Cannot read {:?}: {}.", output_root, error);
+            return records;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let output_dir = entry.path();
+        if !output_dir.is_dir() {
+            continue;
+        }
+
+        let runs = match fs::read_dir(&output_dir) {
+            Ok(runs) => runs,
+            Err(error) => {
+                println!("Cannot read {:?}: {}.", output_dir, error);
+                continue;
+            }
+        };
+        for run in runs.filter_map(Result::ok) {
+            match read_job(&run.path()) {
+                Ok(Some(record)) => records.push(record),
+                Ok(None) => {}
+                Err(error) => println!("Cannot read the job of {:?}: {}.", run.path(), error),
+            }
+        }
+    }
+
+    records.sort_by(|a, b| (&a.name, &a.run).cmp(&(&b.name, &b.run)));
+    records
+}
+
+/// Marks the frame as complete once everything about it is persisted.
+pub fn mark_complete(output_dir: &Path, frame: &str) -> io::Result<()> {
+    let dir = output_dir.join(DONE_DIR);
+    fs::create_dir_all(&dir)?;
+
+    File::create(dir.join(frame)).map(|_| ())
+}
+
+/// Whether the frame was processed, given by the file name of the image.
+pub fn is_complete(output_dir: &Path, frame: &str) -> bool {
+    output_dir.join(DONE_DIR).join(frame).is_file()
+}
+
+/// Marks the frame as failed, so that it is not sent to the worker again when
+/// the service restarts.
+pub fn mark_failed(output_dir: &Path, frame: &str, reason: &str) -> io::Result<()> {
+    let dir = output_dir.join(FAILED_DIR);
+    fs::create_dir_all(&dir)?;
+
+    fs::write(dir.join(frame), reason)
+}
+
+/// Whether the frame could not be processed, given by the file name of the
+/// image.
+pub fn is_failed(output_dir: &Path, frame: &str) -> bool {
+    output_dir.join(FAILED_DIR).join(frame).is_file()
+}
+
+/// Removes the markers of the failed frames of the run, so that they are tried
+/// again.
+pub fn clear_failed(output_dir: &Path) -> io::Result<()> {
+    let dir = output_dir.join(FAILED_DIR);
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    fs::remove_dir_all(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scratch::Scratch;
+    use super::*;
+
+    fn record(name: &str, run: &str) -> JobRecord {
+        JobRecord {
+            name: name.to_string(),
+            run: run.to_string(),
+            created: 0,
+            settings: Settings::default(),
+            frames: vec!["1.png".to_string()],
+        }
+    }
+
+    #[test]
+    fn corrupt_records_are_skipped() {
+        let scratch = Scratch::new();
+        for (name, run) in &[("b", "run_0001"), ("a", "run_0002"), ("a", "run_0001")] {
+            let run_dir = scratch.join(name).join(run);
+            fs::create_dir_all(&run_dir).unwrap();
+            record_job(&run_dir, &record(name, run)).unwrap();
+        }
+        fs::write(scratch.join("b/run_0001").join(JOB_RECORD), b"{\"name\":").unwrap();
+        fs::create_dir_all(scratch.join("c/run_0001")).unwrap();
+        fs::write(scratch.join("c/run_0001").join(JOB_RECORD), b"[]").unwrap();
+        fs::write(scratch.join("notes.txt"), b"").unwrap();
+
+        let runs: Vec<(String, String)> = read_jobs(&scratch)
+            .into_iter()
+            .map(|record| (record.name, record.run))
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("a".to_string(), "run_0001".to_string()),
+                ("a".to_string(), "run_0002".to_string()),
+            ]
+        );
+        assert!(read_jobs(&scratch.join("missing")).is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use super::coco;
+use super::grid::Rect;
+use super::voc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Boxes labelled by people, by the file name of the image they are in.
+pub type Labels = BTreeMap<String, Vec<Rect>>;
+
+/// Highlights which overlap a labelled box at least this much match it.
+pub const IOU_THRESHOLD: f32 = 0.5;
+
+/// Reads labelled boxes from a COCO dataset, or from a directory of Pascal VOC
+/// annotation files.
+pub fn read_labels(path: &Path) -> io::Result<Labels> {
+    if path.is_dir() {
+        voc::read(path)
+    } else {
+        coco::read(path)
+    }
+}
+
+/// How well highlights agree with labelled boxes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Agreement {
+    /// Highlights which match a labelled box.
+    pub true_positives: usize,
+    /// Highlights which match no labelled box.
+    pub false_positives: usize,
+    /// Labelled boxes which no highlight matches.
+    pub false_negatives: usize,
+
+    /// Sum of the intersection over union of the matched pairs.
+    iou_total: f64,
+}
+
+impl Agreement {
+    /// Matches the highlights with the labelled boxes of the same image. Pairs
+    /// which overlap the most are matched first and each box is matched at
+    /// most once.
+    pub fn new(highlights: &[Rect], labelled: &[Rect], threshold: f32) -> Self {
+        let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+        for (i, highlight) in highlights.iter().enumerate() {
+            for (j, label) in labelled.iter().enumerate() {
+                let iou = highlight.intersection_over_union(label);
+                if iou >= threshold {
+                    pairs.push((iou, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut matched_highlights = vec![false; highlights.len()];
+        let mut matched_labels = vec![false; labelled.len()];
+        let mut agreement = Agreement::default();
+        for (iou, i, j) in pairs {
+            if matched_highlights[i] || matched_labels[j] {
+                continue;
+            }
+
+            matched_highlights[i] = true;
+            matched_labels[j] = true;
+            agreement.true_positives += 1;
+            agreement.iou_total += f64::from(iou);
+        }
+
+        agreement.false_positives = highlights.len() - agreement.true_positives;
+        agreement.false_negatives = labelled.len() - agreement.true_positives;
+        agreement
+    }
+
+    /// Adds the counts of another image.
+    pub fn add(&mut self, other: &Agreement) {
+        self.true_positives += other.true_positives;
+        self.false_positives += other.false_positives;
+        self.false_negatives += other.false_negatives;
+        self.iou_total += other.iou_total;
+    }
+
+    pub fn precision(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    /// Harmonic mean of the precision and the recall.
+    pub fn f1(&self) -> f64 {
+        ratio(
+            2 * self.true_positives,
+            2 * self.true_positives + self.false_positives + self.false_negatives,
+        )
+    }
+
+    /// Mean intersection over union of the matched pairs.
+    pub fn mean_iou(&self) -> f64 {
+        if self.true_positives == 0 {
+            return 0.0;
+        }
+
+        self.iou_total / self.true_positives as f64
+    }
+}
+
+/// Agreement in the form which is reported.
+#[derive(Debug, Serialize)]
+pub struct Scores {
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub mean_iou: f64,
+}
+
+impl<'a> From<&'a Agreement> for Scores {
+    fn from(agreement: &Agreement) -> Self {
+        Self {
+            true_positives: agreement.true_positives,
+            false_positives: agreement.false_positives,
+            false_negatives: agreement.false_negatives,
+            precision: agreement.precision(),
+            recall: agreement.recall(),
+            f1: agreement.f1(),
+            mean_iou: agreement.mean_iou(),
+        }
+    }
+}
+
+/// Zero if there is nothing to divide.
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+
+    numerator as f64 / denominator as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn best_pairs_are_matched_first() {
+        let labels = [rect(0, 0, 10, 10), rect(8, 0, 10, 10)];
+        // The first highlight overlaps the second label the most, so the
+        // second highlight gets the first label although it overlaps the
+        // first highlight more than the first label does.
+        let highlights = [rect(7, 0, 10, 10), rect(1, 0, 10, 10)];
+
+        let agreement = Agreement::new(&highlights, &labels, 0.5);
+        assert_eq!(agreement.true_positives, 2);
+        assert_eq!(agreement.false_positives, 0);
+        assert_eq!(agreement.false_negatives, 0);
+        let expected = (9.0 / 11.0 + 9.0 / 11.0) / 2.0;
+        assert!((agreement.mean_iou() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn label_is_matched_once() {
+        let labels = [rect(0, 0, 10, 10)];
+        let highlights = [rect(1, 0, 10, 10), rect(0, 0, 10, 10)];
+
+        let agreement = Agreement::new(&highlights, &labels, 0.5);
+        assert_eq!(agreement.true_positives, 1);
+        assert_eq!(agreement.false_positives, 1);
+        assert_eq!(agreement.false_negatives, 0);
+        assert_eq!(agreement.mean_iou(), 1.0);
+        assert_eq!(agreement.precision(), 0.5);
+        assert_eq!(agreement.recall(), 1.0);
+        assert!((agreement.f1() - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overlap_below_threshold_does_not_match() {
+        let agreement = Agreement::new(&[rect(5, 0, 10, 10)], &[rect(0, 0, 10, 10)], 0.5);
+        assert_eq!(agreement.true_positives, 0);
+        assert_eq!(agreement.false_positives, 1);
+        assert_eq!(agreement.false_negatives, 1);
+    }
+
+    #[test]
+    fn scores_of_empty_inputs_are_zero() {
+        let empty = Agreement::new(&[], &[], 0.5);
+        let no_highlights = Agreement::new(&[], &[rect(0, 0, 10, 10)], 0.5);
+        let no_labels = Agreement::new(&[rect(0, 0, 10, 10)], &[], 0.5);
+
+        for agreement in [empty, no_highlights, no_labels].iter() {
+            assert_eq!(agreement.precision(), 0.0);
+            assert_eq!(agreement.recall(), 0.0);
+            assert_eq!(agreement.f1(), 0.0);
+            assert_eq!(agreement.mean_iou(), 0.0);
+        }
+        assert_eq!(no_highlights.false_negatives, 1);
+        assert_eq!(no_labels.false_positives, 1);
+    }
+
+    #[test]
+    fn agreements_add_up() {
+        let mut total = Agreement::default();
+        total.add(&Agreement::new(
+            &[rect(0, 0, 10, 10)],
+            &[rect(0, 0, 10, 10)],
+            0.5,
+        ));
+        total.add(&Agreement::new(&[rect(0, 0, 10, 10)], &[], 0.5));
+
+        assert_eq!(total.true_positives, 1);
+        assert_eq!(total.false_positives, 1);
+        assert_eq!(total.precision(), 0.5);
+        assert_eq!(total.recall(), 1.0);
+    }
+}
@@ -0,0 +1,166 @@
+use super::adaptive::Candidate;
+use super::canonical_size::Transform;
+use super::grid::Rect;
+use super::point::Point;
+use super::post_processing::Decision;
+use super::pyramid::PyramidMetadata;
+use super::shape_descriptors::ShapeDescriptors;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Describes the highlights found in a single image. It is persisted next to the
+/// highlights so that other services know where each of them came from.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FrameMetadata {
+    /// File name of the original image.
+    pub frame: String,
+
+    /// Dimensions of the original image in pixels.
+    pub width: u32,
+    pub height: u32,
+
+    /// Size of the cells of the bricked heat map in pixels the image was
+    /// processed with. With adaptive cell size it is the one the search chose.
+    pub cell_size: u32,
+
+    /// Cell sizes the adaptive search tried, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cell_size_search: Vec<Candidate>,
+
+    pub highlights: Vec<HighlightMetadata>,
+
+    /// Objects which were merged, suppressed or filtered out after extraction.
+    pub post_processing: Vec<Decision>,
+
+    /// Highlights found at each cell size of the pyramid with the links
+    /// between them. They are not persisted as images.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pyramid: Option<PyramidMetadata>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HighlightMetadata {
+    /// Path of the persisted highlight relative to the output directory, or its
+    /// name within the shard if the highlights are packed into shards.
+    pub file: String,
+
+    /// Path of the binary mask of the object, if the mask was requested. It is
+    /// relative the same way as the highlight.
+    pub mask: Option<String>,
+
+    /// File name of the tar shard in the `shards` directory of the request the
+    /// highlight was packed into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
+
+    /// Maps positions in the original image to positions in the highlight.
+    pub transform: Transform,
+
+    /// Smallest rectangle around the object in cells of the heat map.
+    pub bounds: (Point, Point),
+
+    /// Pixels of the original image covered by the bounds.
+    pub rect: Rect,
+
+    /// Corners of the outer contour of the object in pixels of the original
+    /// image, in clockwise order.
+    pub contour: Vec<Point>,
+
+    /// Number of cells the object consists of.
+    pub cells: usize,
+
+    pub saliency: f32,
+
+    /// Descriptors of the shape of the object computed from its cells.
+    pub shape: ShapeDescriptors,
+}
+
+/// Directory in the directory of a run with the metadata of each frame. It is
+/// separate so that the metadata cannot collide with the record of the run.
+const FRAMES_DIR: &str = "frames";
+
+/// Path to the metadata of the frame given by the file stem of the original
+/// image within the directory of a run.
+pub fn metadata_path(run_dir: &Path, stem: &str) -> PathBuf {
+    run_dir.join(FRAMES_DIR).join(format!("{}.json", stem))
+}
+
+/// Reads the metadata of all frames persisted in the directory of given run,
+/// sorted by the name of the frame. Frames which are still being processed have
+/// no metadata yet and are not included.
+pub fn read_frames(run_dir: &Path) -> io::Result<Vec<FrameMetadata>> {
+    let mut frames: Vec<FrameMetadata> = Vec::new();
+
+    let dir = run_dir.join(FRAMES_DIR);
+    if !dir.is_dir() {
+        return Ok(frames);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let frame = serde_json::from_reader(File::open(&path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        frames.push(frame);
+    }
+
+    frames.sort_by(|a, b| a.frame.cmp(&b.frame));
+    Ok(frames)
+}
+
+/// Metadata of a frame with a highlight for each of given rectangles, for the
+/// tests of the exports.
+#[cfg(test)]
+pub fn frame_with_rects(frame: &str, rects: &[Rect]) -> FrameMetadata {
+    let highlights = rects
+        .iter()
+        .enumerate()
+        .map(|(i, rect)| {
+            let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+            HighlightMetadata {
+                file: format!("{}_{}.png", frame, i),
+                mask: None,
+                shard: None,
+                transform: Transform::identity(*rect),
+                bounds: (Point::new(0, 0), Point::new(0, 0)),
+                rect: *rect,
+                contour: vec![
+                    Point::new(rect.x, rect.y),
+                    Point::new(right, rect.y),
+                    Point::new(right, bottom),
+                    Point::new(rect.x, bottom),
+                ],
+                cells: 1,
+                saliency: 0.5,
+                shape: ShapeDescriptors {
+                    area: 1,
+                    perimeter: 4,
+                    circularity: std::f64::consts::FRAC_PI_4,
+                    centroid: (0.5, 0.5),
+                    orientation: 0.0,
+                    eccentricity: 0.0,
+                    convex_hull: Vec::new(),
+                    solidity: 1.0,
+                    extent: 1.0,
+                    hu_moments: [0.0; 7],
+                },
+            }
+        })
+        .collect();
+
+    FrameMetadata {
+        frame: frame.to_string(),
+        width: 320,
+        height: 180,
+        cell_size: 10,
+        cell_size_search: Vec::new(),
+        highlights,
+        post_processing: Vec::new(),
+        pyramid: None,
+    }
+}
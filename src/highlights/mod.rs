@@ -1,68 +1,202 @@
 extern crate image;
 
+mod adaptive;
+mod canonical_size;
 mod cellular_automaton;
+mod coco;
 mod cut_highlights_from_image;
+mod detect;
+mod encoding;
+mod evaluate;
+mod export;
 mod extract_highlights;
+mod feedback;
 mod find_edges;
+mod grid;
 mod heat_map;
 mod helpers;
+mod inline;
+mod integral_image;
+mod journal;
+mod labels;
+mod metadata;
+mod naming;
+mod npy;
 mod point;
+mod post_processing;
+mod pyramid;
+mod query;
+mod queue;
+mod runs;
+#[cfg(test)]
+pub mod scratch;
+mod settings;
+mod shape_descriptors;
+mod shards;
+mod sweep;
+#[cfg(test)]
+mod synthetic;
 mod visual_object;
+mod voc;
+mod watch;
+mod watershed;
 
 use rayon::ThreadPool;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, PoisonError};
 
-use self::cellular_automaton::cellular_automaton;
-use self::cut_highlights_from_image::cut_highlights_from_image;
-use self::extract_highlights::extract_highlights;
-use self::find_edges::find_edges;
-use self::heat_map::heat_map;
-use self::point::Point;
-use self::visual_object::VisualObject;
+use self::detect::{contour, detect, Detection};
+use self::encoding::encode;
+use self::image::ImageLuma8;
+use self::journal::{mark_complete, mark_failed};
+use self::metadata::{metadata_path, HighlightMetadata};
+use self::naming::Placeholders;
+use self::shape_descriptors::shape_descriptors;
+use self::shards::{Sample, ShardWriter};
+
+pub use self::evaluate::evaluate;
+pub use self::export::{export, ExportFormat};
+pub use self::feedback::{append_feedback, read_feedback, Feedback, FeedbackEntry};
+pub use self::grid::Rect;
+pub use self::helpers::{is_image, is_valid_name};
+pub use self::inline::{identify_inline, image_dimensions, InlineHighlights};
+pub use self::labels::{read_labels, IOU_THRESHOLD};
+pub use self::metadata::{read_frames, FrameMetadata};
+pub use self::query::{list_directories, read_frame, read_highlight, DirectorySummary};
+pub use self::queue::{Mode, Queue, SubmitError, Submitted};
+pub use self::runs::{diff_runs, latest_run, list_runs, summarize_runs, RunDiff, RunSummary};
+pub use self::settings::Settings;
+pub use self::sweep::{Sweep, Sweeper, SWEEP_REPORT};
+pub use self::watch::{validate_schedule, Pass, REWATCH_SCHEDULE};
+
+/// Request to process a directory of images. It is shared by all of its tasks.
+pub struct Job {
+    /// Directory of the run to which the outputs are persisted.
+    pub output_dir: PathBuf,
+
+    /// Identifier of the run.
+    pub run: String,
+
+    pub settings: Settings,
+
+    /// Number of frames which have not been processed yet.
+    remaining: AtomicUsize,
+
+    /// Shards the highlights are appended to if the settings ask for them.
+    shards: Option<Mutex<ShardWriter>>,
+}
+
+impl Job {
+    /// Creates a job for given number of frames of the run with given
+    /// directory.
+    fn new(output_dir: PathBuf, run: String, settings: Settings, frames: usize) -> Self {
+        let shards = settings.shards.as_ref().map(|sharding| {
+            let dir = output_dir.join("shards");
+            Mutex::new(ShardWriter::new(dir, sharding))
+        });
+
+        Self {
+            output_dir,
+            run,
+            settings,
+            remaining: AtomicUsize::new(frames),
+            shards,
+        }
+    }
+}
+
+/// Single image to be processed by the worker together with the job it belongs
+/// to.
+pub struct Task {
+    pub image: Box<Path>,
+    pub job: Arc<Job>,
+}
 
 /// Starts the worker by opening a channel mailbox. Messages from the web server
 /// are going to be distributed to the thread pool to be processed.
-pub fn listen(consumer: Receiver<Box<Path>>, pool: ThreadPool) {
+pub fn listen(consumer: Receiver<Task>, pool: Arc<ThreadPool>) {
     loop {
         match consumer.recv() {
-            Ok(image) => pool.spawn(move || identify_objects(image)),
+            Ok(task) => pool.spawn(move || identify_objects(task)),
             Err(error) => println!("[Worker] {:?}", error),
         }
     }
 }
 
-pub fn identify_objects(path: Box<Path>) {
+pub fn identify_objects(task: Task) {
+    let Task { image: path, job } = task;
+    let _processed = Processed(&job);
     println!("Identifying image at {:?}.", path);
-    let file_stem = path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .expect("Provided image has no file stem.");
-    let dir = path
-        .parent()
-        .and_then(|p| p.file_name())
-        .and_then(|s| s.to_str())
-        .expect("Malformed image path.");
-    let image = image::open(Box::clone(&path)).expect("Could not open image");
-
-    // Converts the image to grayscale and finds edges within the picture. Works
-    // only with bright images. Resulting image has white background with dark
-    // edges highlighted.
-    let edge_detector = find_edges(&image);
-
-    // From the bricked heat map creates more detailed one where each cell is half
-    // of the size of those in the bricked heat map. This multi-dimensional vector
-    // represents density of edges in the original image.
-    // Also returns maximum heat observed in the map and an average heat. This is
-    // used for calculating the rules of the cellular automaton.
-    let (heat_map, heat_max, heat_mean) = heat_map(&edge_detector);
-
-    // Stabilizes each cell into one of two states.
-    let point_map = cellular_automaton(heat_map, heat_max, heat_mean);
-
-    // Finds objects using a recursive flood fill method.
-    let mut highlights: Vec<VisualObject> = Vec::new();
-    extract_highlights(point_map, Point::new(0, 0), &mut highlights);
+
+    // A frame which cannot be processed must not take the worker down, and it
+    // is marked so that it is not sent to the worker again after a restart.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| process_frame(&path, &job)))
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Pipeline panicked")));
+    if let Err(error) = result {
+        println!("Cannot process image {:?}: {}.", path, error);
+
+        if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
+            if let Err(error) = mark_failed(&job.output_dir, file_name, &error.to_string()) {
+                println!("Cannot mark image {:?} failed: {}.", path, error);
+            }
+        }
+    }
+}
+
+/// Counts the frame of the job as processed however its task ends, even if it
+/// panics. The last frame of the job closes the shard which is not full yet.
+struct Processed<'a>(&'a Job);
+
+impl<'a> Drop for Processed<'a> {
+    fn drop(&mut self) {
+        let job = self.0;
+        if job.remaining.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        if let Some(shards) = &job.shards {
+            // A frame which panicked while writing has poisoned the lock, but
+            // the samples written before are still worth indexing.
+            let mut shards = shards.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(error) = shards.close() {
+                println!("Cannot close shard of {:?}: {}.", job.output_dir, error);
+            }
+        }
+    }
+}
+
+/// Finds the highlights of the image and persists them with its metadata.
+fn process_frame(path: &Path, job: &Job) -> io::Result<()> {
+    let settings = &job.settings;
+    let (file_stem, file_name, dir) = match (
+        path.file_stem().and_then(|s| s.to_str()),
+        path.file_name().and_then(|s| s.to_str()),
+        path.parent()
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str()),
+    ) {
+        (Some(file_stem), Some(file_name), Some(dir)) => (file_stem, file_name, dir),
+        _ => return Err(invalid_data("Malformed image path")),
+    };
+    let image = image::open(path).map_err(invalid_data)?;
+
+    let Detection {
+        width,
+        height,
+        cell_size,
+        cell_size_search,
+        pyramid,
+        grid,
+        mut highlights,
+        decisions,
+        crops,
+        ..
+    } = detect(image, settings);
 
     println!(
         "Found {} highlights for image {}/{}.",
@@ -71,13 +205,225 @@ pub fn identify_objects(path: Box<Path>) {
         file_stem
     );
 
-    for (i, highlight) in cut_highlights_from_image(highlights, image)
-        .iter()
-        .enumerate()
-    {
-        let output_path = format!("/etc/harriet/output/{}/{}_{}.png", dir, file_stem, &i);
-        highlight
-            .save(output_path)
-            .expect("Cannot persist highlight");
+    let mut metadata = FrameMetadata {
+        frame: file_name.to_string(),
+        width,
+        height,
+        cell_size,
+        cell_size_search,
+        highlights: Vec::new(),
+        pyramid,
+        post_processing: decisions,
+    };
+
+    let extension = settings.encoding.format.extension();
+
+    // Rank of each highlight by its saliency, the most salient being first.
+    let mut ranks: Vec<usize> = vec![0; highlights.len()];
+    let mut by_saliency: Vec<usize> = (0..highlights.len()).collect();
+    by_saliency.sort_by(|a, b| highlights[*b].saliency.total_cmp(&highlights[*a].saliency));
+    for (rank, index) in by_saliency.into_iter().enumerate() {
+        ranks[index] = rank;
+    }
+
+    // Encoded files of each highlight. They are written once the metadata of
+    // the highlight is known, so that shards can store both together.
+    let mut samples: Vec<Sample> = Vec::new();
+    for (i, (crop, highlight)) in crops.iter().zip(highlights.iter_mut()).enumerate() {
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let bounds = highlight
+            .bounds()
+            .ok_or_else(|| invalid_data("Highlight is empty"))?;
+        let rect = grid.bounds_to_pixels(bounds, 0);
+        let name = settings
+            .naming
+            .render(&Placeholders {
+                dir,
+                run: &job.run,
+                stem: file_stem,
+                index: i,
+                rank: ranks[i],
+                cell_size,
+                rect,
+            })
+            .map_err(invalid_data)?;
+
+        // Lets a highlight identify itself even when separated from metadata.
+        let text = [
+            ("Frame", format!("{}/{}", dir, file_name)),
+            ("Run", job.run.clone()),
+            ("Index", i.to_string()),
+            ("Rect", serde_json::to_string(&rect)?),
+            ("CellSize", cell_size.to_string()),
+        ];
+
+        // Samples in shards follow the WebDataset convention, where everything
+        // after the first dot of the file name is the extension.
+        let (key, file, mask_file) = if job.shards.is_some() {
+            let key = name.replace('.', "_");
+            let file = format!("{}.{}", key, extension);
+            let mask_file = format!("{}.mask.{}", key, extension);
+            (key, file, mask_file)
+        } else {
+            let file = format!("{}.{}", name, extension);
+            let mask_file = format!("{}_mask.{}", name, extension);
+            (name, file, mask_file)
+        };
+
+        let bytes = encode(&crop.image, &settings.encoding, &text).map_err(invalid_data)?;
+        files.push((file.clone(), bytes));
+
+        let mask = match &crop.mask {
+            None => None,
+            Some(mask) => {
+                let bytes = encode(&ImageLuma8(mask.clone()), &settings.encoding, &text)
+                    .map_err(invalid_data)?;
+                files.push((mask_file.clone(), bytes));
+                Some(mask_file)
+            }
+        };
+
+        metadata.highlights.push(HighlightMetadata {
+            file,
+            mask,
+            shard: None,
+            transform: crop.transform.clone(),
+            bounds,
+            rect,
+            contour: contour(highlight, &grid),
+            cells: highlight.points.len(),
+            saliency: highlight.saliency,
+            shape: shape_descriptors(highlight)
+                .ok_or_else(|| invalid_data("Highlight is empty"))?,
+        });
+        samples.push(Sample { key, files });
+    }
+
+    match &job.shards {
+        None => {
+            for (file, bytes) in samples.into_iter().flat_map(|sample| sample.files) {
+                persist(&job.output_dir.join(&file), bytes)?;
+            }
+        }
+        Some(shards) => {
+            // Highlights of the frame are written together, while other frames
+            // wait for the lock.
+            let mut shards = shards.lock().expect("Shard writer is poisoned");
+            for (highlight, mut sample) in metadata.highlights.iter_mut().zip(samples) {
+                let json = serde_json::to_vec(&*highlight)?;
+                sample.files.push((format!("{}.json", sample.key), json));
+
+                let shard = shards.write(sample)?;
+                highlight.shard = Some(shard);
+            }
+        }
+    }
+
+    // The metadata is written last, once all highlights are persisted.
+    let metadata_path = metadata_path(&job.output_dir, file_stem);
+    // It is renamed into place, so that a frame interrupted by a restart is not
+    // mistaken for a complete one.
+    let temporary_path = metadata_path.with_extension("json.tmp");
+    persist_dir(&metadata_path)?;
+    let metadata_file = File::create(&temporary_path)?;
+    serde_json::to_writer_pretty(metadata_file, &metadata)?;
+    fs::rename(temporary_path, metadata_path)?;
+    mark_complete(&job.output_dir, file_name)
+}
+
+/// Writes the bytes into given file. Creates the directories on the way if the
+/// naming template nests the highlights.
+fn persist(path: &Path, bytes: Vec<u8>) -> io::Result<()> {
+    persist_dir(path)?;
+    fs::write(path, bytes)
+}
+
+/// Creates the directories on the way to given file.
+fn persist_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::journal::{is_complete, is_failed};
+    use super::scratch::Scratch;
+    use super::synthetic::{generate, SceneSpec};
+    use super::*;
+
+    fn task(input_dir: &Path, frame: &str, job: &Arc<Job>) -> Task {
+        Task {
+            image: input_dir.join(frame).into_boxed_path(),
+            job: Arc::clone(job),
+        }
+    }
+
+    #[test]
+    fn invalid_frame_is_marked_failed() {
+        let scratch = Scratch::new();
+        let (input_dir, run_dir) = (scratch.join("in"), scratch.join("out"));
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("broken.png"), b"not an image").unwrap();
+        generate(&SceneSpec {
+            width: 160,
+            height: 90,
+            objects: 2,
+            seed: 1,
+        })
+        .image
+        .save(input_dir.join("scene.png"))
+        .unwrap();
+
+        let job = Arc::new(Job::new(
+            run_dir.clone(),
+            "run_0001".into(),
+            Settings::default(),
+            2,
+        ));
+        identify_objects(task(&input_dir, "broken.png", &job));
+        identify_objects(task(&input_dir, "scene.png", &job));
+
+        assert!(is_failed(&run_dir, "broken.png"));
+        assert!(!is_complete(&run_dir, "broken.png"));
+        assert!(is_complete(&run_dir, "scene.png"));
+        assert!(!is_failed(&run_dir, "scene.png"));
+    }
+
+    #[test]
+    fn failed_last_frame_closes_shards() {
+        let scratch = Scratch::new();
+        let (input_dir, run_dir) = (scratch.join("in"), scratch.join("out"));
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("broken.png"), b"not an image").unwrap();
+        generate(&SceneSpec {
+            width: 160,
+            height: 90,
+            objects: 2,
+            seed: 1,
+        })
+        .image
+        .save(input_dir.join("scene.png"))
+        .unwrap();
+
+        let settings = Settings {
+            shards: Some(Default::default()),
+            ..Settings::default()
+        };
+        let job = Arc::new(Job::new(run_dir.clone(), "run_0001".into(), settings, 2));
+        identify_objects(task(&input_dir, "scene.png", &job));
+        assert!(!run_dir.join("shards/index.json").exists());
+
+        // The index is written even though the last frame of the job fails.
+        identify_objects(task(&input_dir, "broken.png", &job));
+        assert!(run_dir.join("shards/index.json").is_file());
     }
 }
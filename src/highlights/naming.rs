@@ -0,0 +1,292 @@
+use super::grid::Rect;
+use serde::{Deserialize, Serialize};
+
+/// Placeholders which can be used in the template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Dir,
+    Run,
+    Stem,
+    Index,
+    Rank,
+    Track,
+    CellSize,
+    X,
+    Y,
+    W,
+    H,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dir" => Some(Placeholder::Dir),
+            "run" => Some(Placeholder::Run),
+            "stem" => Some(Placeholder::Stem),
+            "index" => Some(Placeholder::Index),
+            "rank" => Some(Placeholder::Rank),
+            "track" => Some(Placeholder::Track),
+            "cell_size" => Some(Placeholder::CellSize),
+            "x" => Some(Placeholder::X),
+            "y" => Some(Placeholder::Y),
+            "w" => Some(Placeholder::W),
+            "h" => Some(Placeholder::H),
+            _ => None,
+        }
+    }
+
+    fn value(self, values: &Placeholders) -> String {
+        match self {
+            Placeholder::Dir => values.dir.to_string(),
+            Placeholder::Run => values.run.to_string(),
+            Placeholder::Stem => values.stem.to_string(),
+            Placeholder::Index => values.index.to_string(),
+            Placeholder::Rank => values.rank.to_string(),
+            Placeholder::Track => track(values),
+            Placeholder::CellSize => values.cell_size.to_string(),
+            Placeholder::X => values.rect.x.to_string(),
+            Placeholder::Y => values.rect.y.to_string(),
+            Placeholder::W => values.rect.width.to_string(),
+            Placeholder::H => values.rect.height.to_string(),
+        }
+    }
+}
+
+/// Part of a parsed template.
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(Placeholder),
+}
+
+/// How the persisted highlights are named.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Naming {
+    /// Path of each highlight relative to the directory of the run, without the
+    /// extension. Placeholders in curly braces are replaced with the values of
+    /// the highlight. Slashes create nested directories.
+    pub template: String,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            template: "{stem}_{index}".to_string(),
+        }
+    }
+}
+
+/// Values the placeholders are replaced with.
+pub struct Placeholders<'a> {
+    /// Name of the directory with the original images.
+    pub dir: &'a str,
+    /// Identifier of the run the highlight belongs to.
+    pub run: &'a str,
+    /// File name of the original image without extension.
+    pub stem: &'a str,
+    /// Order of the highlight in the metadata.
+    pub index: usize,
+    /// Order of the highlight by saliency, 0 being the most salient one.
+    pub rank: usize,
+    /// Size of the cells the highlight was found with, in pixels.
+    pub cell_size: u32,
+    /// Rectangle of the highlight in the original image.
+    pub rect: Rect,
+}
+
+impl Naming {
+    /// Checks that the template only uses known placeholders, cannot escape the
+    /// output directory and gives each highlight a unique name. Placeholders
+    /// have to be separated, as `{stem}{index}` gives the same name to stem `a1`
+    /// with index 1 and stem `a` with index 11.
+    pub fn validate(&self) -> Result<(), String> {
+        let placeholders: Vec<Placeholder> = parse(&self.template)?
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Placeholder(placeholder) => Some(placeholder),
+                Segment::Text(_) => None,
+            })
+            .collect();
+
+        if !placeholders.contains(&Placeholder::Stem)
+            || !(placeholders.contains(&Placeholder::Index)
+                || placeholders.contains(&Placeholder::Rank))
+        {
+            return Err("Template has to contain {stem} and either {index} or {rank}.".to_string());
+        }
+
+        if self.template.starts_with('/')
+            || self
+                .template
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err("Template has to be a relative path.".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the placeholders in the template with given values.
+    pub fn render(&self, values: &Placeholders) -> Result<String, String> {
+        let mut output = String::new();
+        for segment in parse(&self.template)? {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Placeholder(placeholder) => output.push_str(&placeholder.value(values)),
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Identifier of the track the highlight belongs to. Highlights are not tracked
+/// across frames yet, so each one is a track of its own, identified by its
+/// frame and rectangle. The identifier is the same in every run which finds the
+/// same highlight.
+fn track(values: &Placeholders) -> String {
+    let key = format!(
+        "{}/{}/{}_{}_{}_{}",
+        values.dir,
+        values.stem,
+        values.rect.x,
+        values.rect.y,
+        values.rect.width,
+        values.rect.height
+    );
+
+    // FNV-1a, whose output does not depend on the version of the compiler.
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Splits the template into text and placeholders.
+fn parse(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            None => return Err("Unclosed placeholder in template.".to_string()),
+            Some(end) => start + end,
+        };
+
+        let name = &rest[(start + 1)..end];
+        if name.contains('{') || rest[..start].contains('}') {
+            return Err("Mismatched braces in template.".to_string());
+        }
+
+        let placeholder = Placeholder::from_name(name)
+            .ok_or_else(|| format!("Unknown placeholder {{{}}} in template.", name))?;
+
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        } else if let Some(Segment::Placeholder(_)) = segments.last() {
+            return Err("Placeholders in template have to be separated.".to_string());
+        }
+
+        segments.push(Segment::Placeholder(placeholder));
+        rest = &rest[(end + 1)..];
+    }
+
+    if rest.contains('}') {
+        return Err("Mismatched braces in template.".to_string());
+    }
+
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(template: &str) -> Naming {
+        Naming {
+            template: template.to_string(),
+        }
+    }
+
+    fn values(stem: &str, x: u32) -> Placeholders {
+        Placeholders {
+            dir: "clip",
+            run: "run_0002",
+            stem,
+            index: 3,
+            rank: 0,
+            cell_size: 10,
+            rect: Rect {
+                x,
+                y: 20,
+                width: 30,
+                height: 40,
+            },
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let template = naming("{dir}/{run}/{stem}/{index}_{rank}_{cell_size}_{x}_{y}_{w}_{h}");
+        assert_eq!(
+            template.render(&values("001", 10)).unwrap(),
+            "clip/run_0002/001/3_0_10_10_20_30_40"
+        );
+        assert_eq!(
+            naming("{stem}_{index}").render(&values("001", 10)).unwrap(),
+            "001_3"
+        );
+    }
+
+    #[test]
+    fn track_is_stable_per_highlight() {
+        let track = naming("{track}").render(&values("001", 10)).unwrap();
+        assert_eq!(track.len(), 16);
+        assert!(track.chars().all(|c| c.is_ascii_hexdigit()));
+
+        // The run and the order of the highlight do not change it.
+        let mut other_run = values("001", 10);
+        other_run.run = "run_0003";
+        other_run.index = 7;
+        assert_eq!(naming("{track}").render(&other_run).unwrap(), track);
+
+        assert_ne!(naming("{track}").render(&values("002", 10)).unwrap(), track);
+        assert_ne!(naming("{track}").render(&values("001", 11)).unwrap(), track);
+    }
+
+    #[test]
+    fn validates_templates() {
+        for valid in &[
+            "{stem}_{index}",
+            "{stem}/{rank}",
+            "{dir}/{stem}_{index}_{track}",
+        ] {
+            assert!(naming(valid).validate().is_ok(), "{}", valid);
+        }
+
+        for invalid in &[
+            // Not unique.
+            "{stem}",
+            "{stem}_{track}",
+            "{index}",
+            "{stem}{index}",
+            "{dir}/{stem}_{index}{track}",
+            // Unknown or malformed placeholders.
+            "{stem}_{index}_{frame}",
+            "{stem}_{index",
+            "{stem}_index}",
+            "{stem}_{{index}}",
+            // Escapes the directory of the run.
+            "/{stem}_{index}",
+            "../{stem}_{index}",
+            "{stem}//{index}",
+        ] {
+            assert!(naming(invalid).validate().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use super::image::{self, DynamicImage, GenericImageView};
+use super::metadata::FrameMetadata;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Magic string and version 1.0 of the format.
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+/// Number of highlights in each shard if not given otherwise.
+pub const SHARD_SIZE: usize = 1024;
+
+/// Packs the highlights of the frames into NumPy arrays of shape `N×H×W×C` of
+/// `uint8`, at most `shard_size` highlights each. Each shard `shard_{i}.npy`
+/// comes with `shard_{i}_meta.npy`, a structured array whose rows describe the
+/// highlights at the same positions.
+///
+/// The highlights have to be of the same size, therefore the directory has to
+/// be processed with a canonical size. Masked highlights have 4 channels,
+/// others 3.
+pub fn write(
+    frames: &[FrameMetadata],
+    run_dir: &Path,
+    dir: &Path,
+    shard_size: usize,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let highlights: Vec<(&FrameMetadata, usize)> = frames
+        .iter()
+        .flat_map(|frame| (0..frame.highlights.len()).map(move |index| (frame, index)))
+        .collect();
+
+    // Frames are stored as fixed length strings, long enough for all of them.
+    let frame_length = frames
+        .iter()
+        .map(|frame| frame.frame.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut shape: Option<(u32, u32, usize)> = None;
+    for (shard, chunk) in highlights.chunks(shard_size.max(1)).enumerate() {
+        let mut pixels: Vec<u8> = Vec::new();
+        let mut rows: Vec<u8> = Vec::new();
+
+        for (frame, index) in chunk.iter() {
+            let highlight = &frame.highlights[*index];
+            if highlight.shard.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Highlights are packed into shards",
+                ));
+            }
+
+            let image = image::open(run_dir.join(&highlight.file))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+            let (width, height) = image.dimensions();
+            let channels = shape.map(|(_, _, c)| c).unwrap_or_else(|| channels(&image));
+            if *shape.get_or_insert((width, height, channels)) != (width, height, channels) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Highlights are not of the same size, use a canonical size",
+                ));
+            }
+
+            pixels.extend(if channels == 4 {
+                image.to_rgba().into_raw()
+            } else {
+                image.to_rgb().into_raw()
+            });
+
+            // The row follows the structure of the metadata dtype.
+            for c in frame
+                .frame
+                .chars()
+                .chain(std::iter::repeat('\0'))
+                .take(frame_length)
+            {
+                rows.extend(&(c as u32).to_le_bytes());
+            }
+            let rect = highlight.rect;
+            for value in [*index as u32, rect.x, rect.y, rect.width, rect.height].iter() {
+                rows.extend(&value.to_le_bytes());
+            }
+            rows.extend(&highlight.saliency.to_le_bytes());
+        }
+
+        let (width, height, channels) = shape.expect("Shard cannot be empty");
+        let pixels_shape = format!("({}, {}, {}, {})", chunk.len(), height, width, channels);
+        let meta_descr = format!(
+            concat!(
+                "[('frame', '<U{}'), ('index', '<u4'), ('x', '<u4'), ('y', '<u4'), ",
+                "('w', '<u4'), ('h', '<u4'), ('saliency', '<f4')]"
+            ),
+            frame_length
+        );
+
+        fs::write(
+            dir.join(format!("shard_{:04}.npy", shard)),
+            array("'|u1'", &pixels_shape, pixels),
+        )?;
+        fs::write(
+            dir.join(format!("shard_{:04}_meta.npy", shard)),
+            array(&meta_descr, &format!("({},)", chunk.len()), rows),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Number of channels the highlights are stored with.
+fn channels(image: &DynamicImage) -> usize {
+    match image {
+        DynamicImage::ImageRgba8(_)
+        | DynamicImage::ImageBgra8(_)
+        | DynamicImage::ImageLumaA8(_) => 4,
+        _ => 3,
+    }
+}
+
+/// Prepends the header of the format to the raw data of an array. The header is
+/// a Python dictionary padded so that the data is aligned to 64 bytes.
+fn array(descr: &str, shape: &str, data: Vec<u8>) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': {}, 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    let unpadded = MAGIC.len() + 2 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut bytes: Vec<u8> = MAGIC.to_vec();
+    bytes.extend(&(header.len() as u16).to_le_bytes());
+    bytes.extend(header.bytes());
+    bytes.extend(data);
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::Rect;
+    use super::super::image::{ImageRgb8, Rgb, RgbImage};
+    use super::super::metadata::frame_with_rects;
+    use super::super::scratch::Scratch;
+    use super::*;
+
+    /// Header of the array as text and the data after it.
+    fn parse(bytes: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+        let length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = String::from_utf8(bytes[10..10 + length].to_vec()).unwrap();
+        (header, &bytes[10 + length..])
+    }
+
+    #[test]
+    fn header_aligns_data() {
+        for shape in &["(1,)", "(1024, 64, 64, 4)", "(12345678, 3)"] {
+            let bytes = array("'|u1'", shape, vec![7; 3]);
+            let (header, data) = parse(&bytes);
+
+            assert_eq!((bytes.len() - data.len()) % 64, 0);
+            assert_eq!(data, &[7, 7, 7]);
+            assert!(header.ends_with('\n'));
+            assert_eq!(
+                header.trim_end(),
+                format!(
+                    "{{'descr': '|u1', 'fortran_order': False, 'shape': {}, }}",
+                    shape
+                )
+            );
+        }
+    }
+
+    fn rect(x: u32) -> Rect {
+        Rect {
+            x,
+            y: 0,
+            width: 4,
+            height: 2,
+        }
+    }
+
+    #[test]
+    fn highlights_are_packed_into_shards() {
+        let scratch = Scratch::new();
+        let frames = vec![
+            frame_with_rects("1.png", &[rect(0), rect(10)]),
+            frame_with_rects("10.png", &[rect(20)]),
+        ];
+        for frame in frames.iter() {
+            for highlight in frame.highlights.iter() {
+                let image = RgbImage::from_pixel(4, 2, Rgb([1, 2, 3]));
+                ImageRgb8(image)
+                    .save(scratch.join(&highlight.file))
+                    .unwrap();
+            }
+        }
+
+        let dir = scratch.join("npy");
+        write(&frames, &scratch, &dir, 2).unwrap();
+
+        let first = fs::read(dir.join("shard_0000.npy")).unwrap();
+        let (header, data) = parse(&first);
+        assert!(header.contains("'shape': (2, 2, 4, 3)"));
+        assert_eq!(data.len(), 2 * 2 * 4 * 3);
+        assert_eq!(&data[..3], &[1, 2, 3]);
+        let second = fs::read(dir.join("shard_0001.npy")).unwrap();
+        let (header, data) = parse(&second);
+        assert!(header.contains("'shape': (1, 2, 4, 3)"));
+        assert_eq!(data.len(), 2 * 4 * 3);
+
+        // Frame of 6 characters, index, rectangle and saliency.
+        let meta = fs::read(dir.join("shard_0000_meta.npy")).unwrap();
+        let (header, rows) = parse(&meta);
+        assert!(header.contains("('frame', '<U6')") && header.contains("'shape': (2,)"));
+        let row = 6 * 4 + 6 * 4;
+        assert_eq!(rows.len(), 2 * row);
+        assert_eq!(&rows[..8], &[b'1', 0, 0, 0, b'.', 0, 0, 0]);
+        let x = &rows[row + 6 * 4 + 4..row + 6 * 4 + 8];
+        assert_eq!(u32::from_le_bytes([x[0], x[1], x[2], x[3]]), 10);
+        assert!(!dir.join("shard_0002.npy").exists());
+    }
+
+    #[test]
+    fn highlights_of_different_sizes_are_rejected() {
+        let scratch = Scratch::new();
+        let frames = vec![frame_with_rects("1.png", &[rect(0), rect(10)])];
+        for (i, highlight) in frames[0].highlights.iter().enumerate() {
+            let image = RgbImage::new(4 + i as u32, 2);
+            ImageRgb8(image)
+                .save(scratch.join(&highlight.file))
+                .unwrap();
+        }
+
+        assert!(write(&frames, &scratch, &scratch.join("npy"), 8).is_err());
+    }
+}
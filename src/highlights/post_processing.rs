@@ -0,0 +1,436 @@
+use super::point::Point;
+use super::shape_descriptors::shape_descriptors;
+use super::visual_object::VisualObject;
+use serde::{Deserialize, Serialize};
+
+/// Rectangle in cells of the heat map given by its lowest and highest point.
+type Bounds = (Point, Point);
+
+/// Which of the post processing steps should run and with what thresholds. The
+/// steps which are `None` are skipped.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostProcessing {
+    /// Two objects are merged into one if their rectangles, expanded by one cell
+    /// in each direction, overlap with intersection over union above this value.
+    pub merge_iou: Option<f32>,
+
+    /// An object is suppressed if it overlaps with a more salient object with
+    /// intersection over union above this value.
+    pub nms_iou: Option<f32>,
+
+    /// Minimum and maximum number of cells of the rectangle around the object.
+    pub min_area: Option<u32>,
+    pub max_area: Option<u32>,
+
+    /// Minimum and maximum ratio of the width of the object to its height.
+    pub min_aspect_ratio: Option<f32>,
+    pub max_aspect_ratio: Option<f32>,
+
+    /// Minimum ratio of the area of the object to the area of its convex hull.
+    /// Noisy and frayed objects have low solidity.
+    pub min_solidity: Option<f32>,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            merge_iou: None,
+            nms_iou: None,
+            // Objects made of a single cell are not worth looking at.
+            min_area: Some(2),
+            max_area: None,
+            min_aspect_ratio: None,
+            max_aspect_ratio: None,
+            min_solidity: None,
+        }
+    }
+}
+
+/// Records why an object was changed or removed by the post processing.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Decision {
+    Merged {
+        bounds: Bounds,
+        with: Bounds,
+        into: Bounds,
+        iou: f32,
+    },
+    Suppressed {
+        bounds: Bounds,
+        saliency: f32,
+        by: Bounds,
+        iou: f32,
+    },
+    Filtered {
+        bounds: Bounds,
+        reason: FilterReason,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterReason {
+    MinArea,
+    MaxArea,
+    MinAspectRatio,
+    MaxAspectRatio,
+    MinSolidity,
+}
+
+/// Merges overlapping objects, suppresses those which overlap with more salient
+/// ones and finally removes objects of unwanted size or shape. Returns the
+/// objects which made it through and the decisions taken along the way.
+pub fn post_process(
+    mut objects: Vec<VisualObject>,
+    settings: &PostProcessing,
+) -> (Vec<VisualObject>, Vec<Decision>) {
+    let mut decisions: Vec<Decision> = Vec::new();
+
+    if let Some(threshold) = settings.merge_iou {
+        objects = merge_overlapping(objects, threshold, &mut decisions);
+    }
+
+    if let Some(threshold) = settings.nms_iou {
+        objects = suppress_non_maximum(objects, threshold, &mut decisions);
+    }
+
+    let objects = objects
+        .into_iter()
+        .filter_map(|mut object| {
+            let bounds = object.bounds()?;
+
+            match filter_reason(&mut object, settings) {
+                None => Some(object),
+                Some(reason) => {
+                    decisions.push(Decision::Filtered { bounds, reason });
+                    None
+                }
+            }
+        })
+        .collect();
+
+    (objects, decisions)
+}
+
+/// Merges objects whose expanded rectangles overlap enough until there is no
+/// such pair left. Each object is compared with all others, and once more after
+/// each merge as its rectangle grows. The merged object takes the place of the
+/// earlier one of the pair.
+fn merge_overlapping(
+    mut objects: Vec<VisualObject>,
+    threshold: f32,
+    decisions: &mut Vec<Decision>,
+) -> Vec<VisualObject> {
+    let mut bounds: Vec<Bounds> = objects.iter_mut().filter_map(|o| o.bounds()).collect();
+    // Objects which overlap none of the others. An object can only start to
+    // overlap one which is done when it grows, and then it is compared again.
+    let mut done: Vec<bool> = vec![false; objects.len()];
+
+    while let Some(current) = done.iter().position(|done| !done) {
+        let overlap = (0..bounds.len())
+            .filter(|other| *other != current)
+            .map(|other| {
+                let iou = intersection_over_union(expand(bounds[current]), expand(bounds[other]));
+                (other, iou)
+            })
+            .find(|(_, iou)| *iou > threshold);
+
+        let (other, iou) = match overlap {
+            None => {
+                done[current] = true;
+                continue;
+            }
+            Some(overlap) => overlap,
+        };
+
+        let (i, j) = (current.min(other), current.max(other));
+        let later = objects.remove(j);
+        let later_bounds = bounds.remove(j);
+        done.remove(j);
+
+        let mut merged = objects.remove(i).merge(later);
+        let merged_bounds = merged.bounds().expect("Merged object is empty");
+
+        decisions.push(Decision::Merged {
+            bounds: bounds[i],
+            with: later_bounds,
+            into: merged_bounds,
+            iou,
+        });
+
+        objects.insert(i, merged);
+        bounds[i] = merged_bounds;
+        done[i] = false;
+    }
+
+    objects
+}
+
+/// Keeps the most salient objects and removes every object which overlaps with
+/// an already kept one more than the threshold allows.
+fn suppress_non_maximum(
+    mut objects: Vec<VisualObject>,
+    threshold: f32,
+    decisions: &mut Vec<Decision>,
+) -> Vec<VisualObject> {
+    // Sort is stable, therefore objects of equal saliency keep their order.
+    objects.sort_by(|a, b| b.saliency.total_cmp(&a.saliency));
+
+    let mut kept: Vec<VisualObject> = Vec::new();
+    let mut kept_bounds: Vec<Bounds> = Vec::new();
+
+    for mut object in objects.into_iter() {
+        let bounds = match object.bounds() {
+            None => continue,
+            Some(bounds) => bounds,
+        };
+
+        let suppressor = kept_bounds
+            .iter()
+            .map(|other| (*other, intersection_over_union(bounds, *other)))
+            .find(|(_, iou)| *iou > threshold);
+
+        match suppressor {
+            Some((by, iou)) => decisions.push(Decision::Suppressed {
+                bounds,
+                saliency: object.saliency,
+                by,
+                iou,
+            }),
+            None => {
+                kept.push(object);
+                kept_bounds.push(bounds);
+            }
+        }
+    }
+
+    kept
+}
+
+/// Returns the first filter given object fails, if any.
+fn filter_reason(object: &mut VisualObject, settings: &PostProcessing) -> Option<FilterReason> {
+    let (lower, higher) = object.bounds()?;
+    let width = higher.x - lower.x + 1;
+    let height = higher.y - lower.y + 1;
+    let area = width * height;
+    let aspect_ratio = width as f32 / height as f32;
+
+    if settings.min_area.map_or(false, |min| area < min) {
+        Some(FilterReason::MinArea)
+    } else if settings.max_area.map_or(false, |max| area > max) {
+        Some(FilterReason::MaxArea)
+    } else if settings
+        .min_aspect_ratio
+        .map_or(false, |min| aspect_ratio < min)
+    {
+        Some(FilterReason::MinAspectRatio)
+    } else if settings
+        .max_aspect_ratio
+        .map_or(false, |max| aspect_ratio > max)
+    {
+        Some(FilterReason::MaxAspectRatio)
+    } else if settings.min_solidity.map_or(false, |min| {
+        shape_descriptors(object).map_or(false, |shape| (shape.solidity as f32) < min)
+    }) {
+        Some(FilterReason::MinSolidity)
+    } else {
+        None
+    }
+}
+
+/// Expands the rectangle by one cell in each direction, so that objects a cell
+/// apart overlap.
+fn expand((lower, higher): Bounds) -> Bounds {
+    (
+        Point::new(lower.x.max(1) - 1, lower.y.max(1) - 1),
+        Point::new(higher.x + 1, higher.y + 1),
+    )
+}
+
+/// Ratio of the area both rectangles share to the area they cover together.
+/// The bounds are inclusive.
+fn intersection_over_union(a: Bounds, b: Bounds) -> f32 {
+    let area = |(lower, higher): Bounds| {
+        ((higher.x - lower.x + 1) as u64 * (higher.y - lower.y + 1) as u64) as f32
+    };
+
+    let lower = Point::new(a.0.x.max(b.0.x), a.0.y.max(b.0.y));
+    let higher = Point::new(a.1.x.min(b.1.x), a.1.y.min(b.1.y));
+
+    if lower.x > higher.x || lower.y > higher.y {
+        return 0_f32;
+    }
+
+    let intersection = area((lower, higher));
+
+    intersection / (area(a) + area(b) - intersection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filled rectangle of cells from the first to the second corner,
+    /// inclusive.
+    fn object(lower: (u32, u32), higher: (u32, u32), saliency: f32) -> VisualObject {
+        let mut object = VisualObject::new(Point::new(0, 0));
+        for y in lower.1..=higher.1 {
+            for x in lower.0..=higher.0 {
+                object.push(Point::new(x, y));
+            }
+        }
+        object.saliency = saliency;
+        object
+    }
+
+    fn bounds(objects: &mut [VisualObject]) -> Vec<Bounds> {
+        objects.iter_mut().filter_map(|o| o.bounds()).collect()
+    }
+
+    fn settings() -> PostProcessing {
+        PostProcessing {
+            min_area: None,
+            ..PostProcessing::default()
+        }
+    }
+
+    #[test]
+    fn inclusive_intersection_over_union() {
+        let a = (Point::new(0, 0), Point::new(3, 3));
+        let b = (Point::new(2, 0), Point::new(5, 3));
+
+        assert_eq!(intersection_over_union(a, a), 1.0);
+        assert_eq!(intersection_over_union(a, b), 8.0 / 24.0);
+        assert_eq!(
+            intersection_over_union(a, (Point::new(4, 0), Point::new(5, 3))),
+            0.0
+        );
+    }
+
+    #[test]
+    fn merges_objects_close_to_each_other() {
+        let settings = PostProcessing {
+            merge_iou: Some(0.05),
+            ..settings()
+        };
+        // The first two are a cell apart, the third is far away.
+        let objects = vec![
+            object((0, 0), (3, 3), 0.5),
+            object((5, 0), (8, 3), 0.5),
+            object((20, 20), (22, 22), 0.5),
+        ];
+
+        let (mut kept, decisions) = post_process(objects, &settings);
+        assert_eq!(
+            bounds(&mut kept),
+            vec![
+                (Point::new(0, 0), Point::new(8, 3)),
+                (Point::new(20, 20), Point::new(22, 22)),
+            ]
+        );
+        assert_eq!(kept[0].points.len(), 32);
+        match &decisions[..] {
+            [Decision::Merged { into, .. }] => assert_eq!(*into, kept[0].bounds().unwrap()),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn merges_objects_which_overlap_once_grown() {
+        let settings = PostProcessing {
+            merge_iou: Some(0.05),
+            ..settings()
+        };
+        // The strip overlaps neither square alone, only both merged.
+        let objects = vec![
+            object((0, 5), (19, 8), 0.5),
+            object((10, 0), (13, 3), 0.5),
+            object((15, 0), (18, 3), 0.5),
+        ];
+
+        let (mut kept, decisions) = post_process(objects, &settings);
+        assert_eq!(
+            bounds(&mut kept),
+            vec![(Point::new(0, 0), Point::new(19, 8))]
+        );
+        assert_eq!(kept[0].points.len(), 80 + 16 + 16);
+        assert_eq!(decisions.len(), 2);
+    }
+
+    #[test]
+    fn suppresses_less_salient_overlaps() {
+        let settings = PostProcessing {
+            nms_iou: Some(0.4),
+            ..settings()
+        };
+        let objects = vec![
+            object((0, 0), (3, 3), 0.2),
+            object((0, 0), (3, 4), 0.9),
+            object((10, 0), (13, 3), f32::NAN),
+            object((1, 0), (4, 3), 0.5),
+        ];
+
+        let (mut kept, decisions) = post_process(objects, &settings);
+        let kept_bounds = bounds(&mut kept);
+        assert_eq!(kept_bounds.len(), 2);
+        assert!(kept_bounds.contains(&(Point::new(0, 0), Point::new(3, 4))));
+        assert!(kept_bounds.contains(&(Point::new(10, 0), Point::new(13, 3))));
+
+        let suppressed: Vec<f32> = decisions
+            .iter()
+            .map(|decision| match decision {
+                Decision::Suppressed { saliency, by, .. } => {
+                    assert_eq!(*by, (Point::new(0, 0), Point::new(3, 4)));
+                    *saliency
+                }
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(suppressed, vec![0.5, 0.2]);
+    }
+
+    #[test]
+    fn filters_by_size_and_shape() {
+        let settings = PostProcessing {
+            min_area: Some(4),
+            max_area: Some(50),
+            min_aspect_ratio: Some(0.5),
+            max_aspect_ratio: Some(2.0),
+            min_solidity: Some(0.8),
+            ..PostProcessing::default()
+        };
+        let mut ring = object((0, 0), (4, 4), 0.5);
+        ring.points
+            .retain(|p| p.x == 0 || p.y == 0 || p.x == 4 || p.y == 4);
+        let objects = vec![
+            object((0, 0), (0, 0), 0.5),
+            object((0, 0), (9, 9), 0.5),
+            object((0, 0), (0, 4), 0.5),
+            object((0, 0), (4, 0), 0.5),
+            ring,
+            object((0, 0), (2, 3), 0.5),
+        ];
+
+        let (mut kept, decisions) = post_process(objects, &settings);
+        assert_eq!(
+            bounds(&mut kept),
+            vec![(Point::new(0, 0), Point::new(2, 3))]
+        );
+        let reasons: Vec<String> = decisions
+            .iter()
+            .map(|decision| serde_json::to_value(decision).unwrap()["reason"].to_string())
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "\"min_area\"",
+                "\"max_area\"",
+                "\"min_aspect_ratio\"",
+                "\"max_aspect_ratio\"",
+                "\"min_solidity\"",
+            ]
+        );
+    }
+}
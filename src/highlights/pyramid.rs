@@ -0,0 +1,248 @@
+use super::grid::Rect;
+use super::heat_map::validate_cell_size;
+use super::point::Point;
+use serde::{Deserialize, Serialize};
+
+/// Largest number of cell sizes the pyramid can run with.
+pub const MAX_LEVELS: usize = 8;
+
+/// Runs the pipeline at several cell sizes on the same edges and links the
+/// highlights across them by containment. A large object found with coarse
+/// cells then contains its parts found with finer ones.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pyramid {
+    /// Cell sizes of the levels. They are processed from the coarsest to the
+    /// finest regardless of the order.
+    pub cell_sizes: Vec<u32>,
+
+    /// Fraction of the rectangle of a highlight which has to lie within the
+    /// rectangle of a coarser one for it to become its child.
+    pub containment: f32,
+
+    /// Children which overlap their parent with intersection over union of at
+    /// least this much are the same object. Only the finest of them is in the
+    /// flat list. The child is also compared expanded by half of the cell size
+    /// of the parent.
+    pub duplicate_iou: f32,
+}
+
+/// Highlights of all levels of a frame with the links between them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PyramidMetadata {
+    /// Cell sizes of the levels from the coarsest to the finest.
+    pub cell_sizes: Vec<u32>,
+
+    /// Highlights of all levels, the coarsest level first.
+    pub highlights: Vec<ScaleHighlight>,
+
+    /// Indices of the highlights which are not the same object as one of
+    /// their children, so that each object is listed once.
+    pub flat: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScaleHighlight {
+    /// Cell size of the level the highlight was found at.
+    pub cell_size: u32,
+
+    /// Pixels of the original image covered by the bounds.
+    pub rect: Rect,
+
+    /// Smallest rectangle around the object in cells of the heat map of its
+    /// level.
+    pub bounds: (Point, Point),
+
+    /// Number of cells the object consists of.
+    pub cells: usize,
+
+    pub saliency: f32,
+
+    /// Index of the smallest coarser highlight which contains this one.
+    pub parent: Option<usize>,
+
+    /// Indices of the finer highlights this one contains.
+    pub children: Vec<usize>,
+}
+
+impl Default for Pyramid {
+    fn default() -> Self {
+        Self {
+            cell_sizes: vec![14, 10, 8],
+            containment: 0.8,
+            duplicate_iou: 0.6,
+        }
+    }
+}
+
+impl Pyramid {
+    /// Returns the reason why the pyramid cannot be built with these
+    /// parameters.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cell_sizes.is_empty() || self.cell_sizes.len() > MAX_LEVELS {
+            return Err(format!(
+                "Pyramid has to have between 1 and {} levels.",
+                MAX_LEVELS
+            ));
+        }
+
+        for (i, size) in self.cell_sizes.iter().enumerate() {
+            validate_cell_size(*size)?;
+            if self.cell_sizes[..i].contains(size) {
+                return Err(format!("Cell size {} is in the pyramid twice.", size));
+            }
+        }
+
+        for threshold in [self.containment, self.duplicate_iou].iter() {
+            if !(*threshold > 0.0 && *threshold <= 1.0) {
+                return Err("Pyramid thresholds have to be between 0 and 1.".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cell sizes from the coarsest to the finest.
+    pub fn levels(&self) -> Vec<u32> {
+        let mut levels = self.cell_sizes.clone();
+        levels.sort_by(|a, b| b.cmp(a));
+        levels
+    }
+
+    /// Links the highlights of the levels, which are given in the order of
+    /// `levels`. Each highlight becomes the child of the smallest highlight of
+    /// the nearest coarser level which contains it.
+    pub fn link(&self, levels: Vec<Vec<ScaleHighlight>>) -> PyramidMetadata {
+        let mut highlights: Vec<ScaleHighlight> = Vec::new();
+        // Range of the indices of the highlights of each level linked so far.
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+        for level in levels {
+            for mut highlight in level {
+                let index = highlights.len();
+                highlight.parent = ranges
+                    .iter()
+                    .rev()
+                    .filter_map(|(start, end)| {
+                        (*start..*end)
+                            .filter(|parent| {
+                                contains(&highlights[*parent].rect, &highlight.rect)
+                                    >= self.containment
+                            })
+                            .min_by_key(|parent| highlights[*parent].rect.area())
+                    })
+                    .next();
+
+                if let Some(parent) = highlight.parent {
+                    highlights[parent].children.push(index);
+                }
+                highlights.push(highlight);
+            }
+
+            let start = ranges.last().map_or(0, |(_, end)| *end);
+            if highlights.len() > start {
+                ranges.push((start, highlights.len()));
+            }
+        }
+
+        // Highlights are linked coarse to fine, therefore a chain of the same
+        // object keeps only its last highlight. Rectangles of coarse cells can
+        // be loose by up to a cell, so the child is also compared at the
+        // precision of its parent.
+        let flat = (0..highlights.len())
+            .filter(|index| {
+                let highlight = &highlights[*index];
+                let span = highlight.cell_size / 2;
+                !highlight.children.iter().any(|child| {
+                    let child = &highlights[*child].rect;
+                    let iou = highlight
+                        .rect
+                        .intersection_over_union(child)
+                        .max(highlight.rect.intersection_over_union(&expand(child, span)));
+                    iou >= self.duplicate_iou
+                })
+            })
+            .collect();
+
+        PyramidMetadata {
+            cell_sizes: self.levels(),
+            highlights,
+            flat,
+        }
+    }
+}
+
+/// Fraction of the inner rectangle which lies within the outer one.
+fn contains(outer: &Rect, inner: &Rect) -> f32 {
+    let width =
+        (outer.x + outer.width).min(inner.x + inner.width) as i64 - outer.x.max(inner.x) as i64;
+    let height =
+        (outer.y + outer.height).min(inner.y + inner.height) as i64 - outer.y.max(inner.y) as i64;
+    if width <= 0 || height <= 0 || inner.area() == 0 {
+        return 0.0;
+    }
+
+    (width * height) as f32 / inner.area() as f32
+}
+
+/// Rectangle grown by given number of pixels in each direction, clamped at the
+/// top left corner of the image.
+fn expand(rect: &Rect, by: u32) -> Rect {
+    let (x, y) = (rect.x.saturating_sub(by), rect.y.saturating_sub(by));
+    Rect {
+        x,
+        y,
+        width: rect.x + rect.width + by - x,
+        height: rect.y + rect.height + by - y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(cell_size: u32, x: u32, y: u32, width: u32, height: u32) -> ScaleHighlight {
+        ScaleHighlight {
+            cell_size,
+            rect: Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            bounds: (Point::new(0, 0), Point::new(0, 0)),
+            cells: 1,
+            saliency: 0.0,
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn links_by_containment() {
+        let pyramid = Pyramid {
+            cell_sizes: vec![8, 14],
+            ..Pyramid::default()
+        };
+        let linked = pyramid.link(vec![
+            // An object with two parts and another one on its own.
+            vec![highlight(14, 0, 0, 100, 50), highlight(14, 200, 0, 40, 40)],
+            vec![
+                highlight(8, 4, 4, 40, 40),
+                highlight(8, 56, 4, 40, 40),
+                highlight(8, 204, 4, 32, 32),
+                // Mostly outside of any coarse highlight.
+                highlight(8, 90, 40, 40, 40),
+            ],
+        ]);
+
+        assert_eq!(linked.cell_sizes, vec![14, 8]);
+        let parents: Vec<Option<usize>> = linked.highlights.iter().map(|h| h.parent).collect();
+        assert_eq!(parents, vec![None, None, Some(0), Some(0), Some(1), None]);
+        assert_eq!(linked.highlights[0].children, vec![2, 3]);
+
+        // The lone object is the same at both levels, only the finer one is
+        // kept.
+        assert_eq!(linked.flat, vec![0, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,111 @@
+use super::metadata::{metadata_path, read_frames, FrameMetadata};
+use super::runs::list_runs;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use tar::Archive;
+
+/// Overview of a processed directory.
+#[derive(Serialize)]
+pub struct DirectorySummary {
+    pub name: String,
+
+    /// Identifiers of the runs of the directory, oldest first.
+    pub runs: Vec<String>,
+
+    /// Number of frames of the latest run whose metadata is persisted.
+    pub frames: usize,
+
+    /// Number of highlights in those frames.
+    pub highlights: usize,
+
+    /// Number of passes over the directory. Every run is a pass.
+    pub passes: usize,
+}
+
+/// Lists the directories in the output root which have a run, sorted by name.
+/// Directories whose name fails given check are skipped.
+pub fn list_directories<F>(output_root: &Path, is_valid: F) -> io::Result<Vec<DirectorySummary>>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut directories: Vec<DirectorySummary> = Vec::new();
+
+    for entry in fs::read_dir(output_root)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_dir() && is_valid(name) => name.to_string(),
+            _ => continue,
+        };
+
+        let runs = list_runs(&path)?;
+        let frames = match runs.last() {
+            None => continue,
+            Some(run) => read_frames(&path.join(run))?,
+        };
+        directories.push(DirectorySummary {
+            name,
+            passes: runs.len(),
+            runs,
+            frames: frames.len(),
+            highlights: frames.iter().map(|frame| frame.highlights.len()).sum(),
+        });
+    }
+
+    directories.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(directories)
+}
+
+/// Reads the metadata of the frame given by the file stem of the original
+/// image from the directory of given run. Returns `None` if the frame was not
+/// processed yet.
+pub fn read_frame(run_dir: &Path, frame: &str) -> io::Result<Option<FrameMetadata>> {
+    let metadata_path = metadata_path(run_dir, frame);
+    if !metadata_path.is_file() {
+        return Ok(None);
+    }
+
+    let metadata = serde_json::from_reader(File::open(metadata_path)?)?;
+    Ok(Some(metadata))
+}
+
+/// Reads the persisted highlight with given index of the frame, given by the
+/// file stem of the original image. Returns the name of its file, so that the
+/// format can be told, and its bytes. Highlights packed into shards are read
+/// from the shard.
+pub fn read_highlight(
+    run_dir: &Path,
+    frame: &str,
+    index: usize,
+) -> io::Result<Option<(String, Vec<u8>)>> {
+    let metadata = match read_frame(run_dir, frame)? {
+        None => return Ok(None),
+        Some(metadata) => metadata,
+    };
+    let highlight = match metadata.highlights.get(index) {
+        None => return Ok(None),
+        Some(highlight) => highlight,
+    };
+
+    let shard = match &highlight.shard {
+        None => {
+            let bytes = fs::read(run_dir.join(&highlight.file))?;
+            return Ok(Some((highlight.file.clone(), bytes)));
+        }
+        Some(shard) => shard,
+    };
+
+    let shard_path = run_dir.join("shards").join(shard);
+    let mut archive = Archive::new(File::open(shard_path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_str() == Some(highlight.file.as_str()) {
+            let mut bytes: Vec<u8> = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            return Ok(Some((highlight.file.clone(), bytes)));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,629 @@
+use super::feedback::has_feedback;
+use super::helpers::is_image;
+use super::journal::{
+    clear_failed, is_complete, is_failed, read_job, read_jobs, record_job, JobRecord,
+};
+use super::runs::{create_run, latest_run, migrate_baseline, run_number};
+use super::settings::Settings;
+use super::sweep::read_defaults;
+use super::watch::{read_watch, record_watch, scheduled_cell_size, Pass};
+use super::{Job, Task};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What happens to a previous run of the directory. Requests without a mode
+/// create a new run.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Processes the images of the run which are not complete.
+    Resume,
+    /// Also processes images which were added to the directory since.
+    Append,
+    /// Removes all outputs of the run and processes it from scratch.
+    Overwrite,
+}
+
+#[derive(Debug)]
+pub enum SubmitError {
+    /// The directory with the images or the requested run does not exist.
+    NotFound,
+    /// The request cannot be combined with the previous one.
+    Conflict(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for SubmitError {
+    fn from(error: io::Error) -> Self {
+        SubmitError::Io(error)
+    }
+}
+
+/// Run to which the images of a request were sent.
+#[derive(Debug, Serialize)]
+pub struct Submitted {
+    pub run: String,
+
+    /// Number of images sent to the worker.
+    pub frames: usize,
+}
+
+/// Sends the images of requested directories to the worker. Keeps track of the
+/// jobs which are being processed, so that a run is not processed twice at the
+/// same time.
+pub struct Queue {
+    producer: Mutex<Sender<Task>>,
+    input_root: PathBuf,
+    output_root: PathBuf,
+
+    /// Jobs by the name of their directory and run. A job is dropped once its
+    /// last image is processed.
+    active: Mutex<HashMap<String, Slot>>,
+
+    /// Held while a pass is recorded, so that two re-watches of a directory do
+    /// not overwrite each other's record.
+    rewatch: Mutex<()>,
+}
+
+/// Entry of a run among the active jobs.
+enum Slot {
+    /// A request continues the run, but has not queued its images yet.
+    Reserved,
+    /// Images of the job are queued or being processed.
+    Queued(Weak<Job>),
+}
+
+/// Keeps the run reserved for a request until its images are queued. If the
+/// request fails before that, the run is released.
+struct Reservation<'a> {
+    active: &'a Mutex<HashMap<String, Slot>>,
+    key: String,
+}
+
+impl<'a> Drop for Reservation<'a> {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(Slot::Reserved) = active.get(&self.key) {
+            active.remove(&self.key);
+        }
+    }
+}
+
+impl Queue {
+    pub fn new(producer: Sender<Task>, input_root: PathBuf, output_root: PathBuf) -> Self {
+        Self {
+            producer: Mutex::new(producer),
+            input_root,
+            output_root,
+            active: Mutex::new(HashMap::new()),
+            rewatch: Mutex::new(()),
+        }
+    }
+
+    /// Persists the job for the directory with given name and sends its images
+    /// to the worker. Without a mode, the job is a new run of the directory.
+    /// With a mode, it continues given run, or the latest one if none is given.
+    /// Settings which are not given are the defaults of the directory for a new
+    /// run, or those of the previous request when it is resumed.
+    pub fn submit(
+        &self,
+        name: &str,
+        settings: Option<Settings>,
+        mode: Option<Mode>,
+        run: Option<&str>,
+    ) -> Result<Submitted, SubmitError> {
+        let (record, _reservation) = self.prepare(name, settings, mode, run)?;
+
+        let run = record.run.clone();
+        let frames = self.enqueue(record)?;
+        Ok(Submitted { run, frames })
+    }
+
+    /// Persists the job of a request without sending its images to the worker
+    /// yet. A run which is continued stays reserved until the reservation is
+    /// dropped.
+    fn prepare(
+        &self,
+        name: &str,
+        settings: Option<Settings>,
+        mode: Option<Mode>,
+        run: Option<&str>,
+    ) -> Result<(JobRecord, Option<Reservation>), SubmitError> {
+        let input_dir = self.input_root.join(name);
+        if !input_dir.is_dir() {
+            return Err(SubmitError::NotFound);
+        }
+
+        let output_dir = self.output_root.join(name);
+        let previous = match (mode, run) {
+            (None, _) => None,
+            (Some(_), Some(run)) => match read_job(&output_dir.join(run))? {
+                None => return Err(SubmitError::NotFound),
+                Some(record) => Some(record),
+            },
+            (Some(_), None) => match latest_run(&output_dir)? {
+                None => None,
+                Some(run) => read_job(&output_dir.join(run))?,
+            },
+        };
+
+        // Until the images are queued, another request for the same run has
+        // to wait for this one.
+        let reservation = match &previous {
+            Some(previous) => Some(self.reserve(name, &previous.run)?),
+            None => None,
+        };
+
+        let frames = list_frames(&input_dir)?;
+        let defaults = read_defaults(&output_dir)?;
+        let record = match (previous, mode) {
+            (Some(previous), Some(Mode::Overwrite)) => {
+                // The feedback is about highlights which the run would no
+                // longer have.
+                let run_dir = output_dir.join(&previous.run);
+                if has_feedback(&run_dir) {
+                    return Err(SubmitError::Conflict(format!(
+                        "Run {} of {} has feedback and cannot be overwritten.",
+                        previous.run, name
+                    )));
+                }
+                fs::remove_dir_all(&run_dir)?;
+                fs::create_dir(&run_dir)?;
+                new_record(name, previous.run, settings.or(defaults), frames)
+            }
+            (Some(previous), Some(mode)) => {
+                if let Some(settings) = settings {
+                    if !same_settings(&settings, &previous.settings) {
+                        return Err(SubmitError::Conflict(
+                            "Settings differ from the previous request.".to_string(),
+                        ));
+                    }
+                }
+
+                // Frames which failed are tried again when asked for explicitly.
+                clear_failed(&output_dir.join(&previous.run))?;
+
+                let mut record = previous;
+                if mode == Mode::Append {
+                    record.frames.extend(frames);
+                    record.frames.sort();
+                    record.frames.dedup();
+                }
+                record
+            }
+            // The directory has no run to continue yet.
+            (_, _) => {
+                let run = create_run(&output_dir)?;
+                new_record(name, run, settings.or(defaults), frames)
+            }
+        };
+
+        // The job is persisted before the images are queued, so that it can be
+        // resumed if the service restarts in the middle of it.
+        record_job(&output_dir.join(&record.run), &record)?;
+
+        Ok((record, reservation))
+    }
+
+    /// Starts the next pass over the directory as a new run. Every run of the
+    /// directory is a pass, whoever started it, and the number of the run is
+    /// the number of the pass. The cell size of the new one is given by the
+    /// schedule, other settings are the defaults of the directory. The
+    /// schedule has to be valid.
+    pub fn rewatch(&self, name: &str, schedule: &[u32]) -> Result<Pass, SubmitError> {
+        let input_dir = self.input_root.join(name);
+        if !input_dir.is_dir() {
+            return Err(SubmitError::NotFound);
+        }
+
+        let output_dir = self.output_root.join(name);
+        let frames = list_frames(&input_dir)?;
+        let defaults = read_defaults(&output_dir)?.unwrap_or_default();
+
+        // The cell size follows the run which is actually created, even if
+        // another request created one in the meantime.
+        let run = create_run(&output_dir)?;
+        let number = run_number(&run).expect("Runs are numbered");
+        let cell_size = scheduled_cell_size(schedule, number - 1);
+
+        // The schedule decides the cell size, the adaptive search would not
+        // follow it.
+        let settings = Settings {
+            cell_size,
+            adaptive: None,
+            ..defaults
+        };
+        let record = new_record(name, run, Some(settings), frames);
+        let pass = Pass {
+            pass: number,
+            run: record.run.clone(),
+            cell_size,
+            frames: record.frames.len(),
+            created: record.created,
+        };
+
+        // The job and the pass are recorded before the images are queued, so
+        // that a pass which is being processed is never missing from the
+        // record. The run is removed if either cannot be recorded.
+        let recorded = record_job(&output_dir.join(&record.run), &record).and_then(|()| {
+            let _lock = self.rewatch.lock().expect("Re-watch lock is poisoned");
+            let mut watch = read_watch(&output_dir)?;
+            watch.passes.push(pass.clone());
+            record_watch(&output_dir, &watch)
+        });
+        if let Err(error) = recorded {
+            let _ = fs::remove_dir_all(output_dir.join(&record.run));
+            return Err(error.into());
+        }
+
+        self.enqueue(record)?;
+        Ok(pass)
+    }
+
+    /// Sends the images of all jobs in the output root which were interrupted
+    /// by a restart to the worker again. Highlights of directories processed
+    /// before there were runs are moved into their first run beforehand. Jobs
+    /// which cannot be resumed are logged and skipped.
+    pub fn replay(&self) {
+        for name in migrate_baseline(&self.input_root, &self.output_root) {
+            println!("Moved the highlights of {} into its first run.", name);
+        }
+
+        for record in read_jobs(&self.output_root) {
+            let name = format!("{}/{}", record.name, record.run);
+            match self.enqueue(record) {
+                Ok(0) => {}
+                Ok(frames) => println!("Resuming {} unfinished frames of {}.", frames, name),
+                Err(error) => println!("Cannot resume {}: {:?}.", name, error),
+            }
+        }
+    }
+
+    /// Sends the images of the job which are neither complete nor failed to the
+    /// worker. Images which were removed from the directory are skipped.
+    /// Returns the number of images sent.
+    fn enqueue(&self, record: JobRecord) -> Result<usize, SubmitError> {
+        let output_dir = self.output_root.join(&record.name).join(&record.run);
+        let input_dir = self.input_root.join(&record.name);
+        let frames: Vec<&String> = record
+            .frames
+            .iter()
+            .filter(|frame| !is_complete(&output_dir, frame) && !is_failed(&output_dir, frame))
+            .filter(|frame| input_dir.join(frame).is_file())
+            .collect();
+
+        // All images of the request share the same job.
+        let job = Arc::new(Job::new(
+            output_dir.clone(),
+            record.run.clone(),
+            record.settings.clone(),
+            frames.len(),
+        ));
+
+        // A job which was interrupted after its last image still has to close
+        // its shards.
+        if frames.is_empty() {
+            if let Some(shards) = &job.shards {
+                let mut shards = shards.lock().expect("Shard writer is poisoned");
+                shards.close()?;
+            }
+        }
+
+        self.active
+            .lock()
+            .expect("Active jobs are poisoned")
+            .insert(
+                active_key(&record.name, &record.run),
+                Slot::Queued(Arc::downgrade(&job)),
+            );
+
+        // We acquire a mutex lock, clone the producer and immediately drop the lock.
+        let producer = {
+            self.producer
+                .lock()
+                .map(|original| original.clone())
+                .expect("Producer is poisoned")
+        };
+
+        // We send each image path as one message. This helps the worker distribute
+        // the workload into the threadpool.
+        for frame in frames.iter() {
+            let task = Task {
+                image: input_dir.join(frame).into_boxed_path(),
+                job: Arc::clone(&job),
+            };
+            producer.send(task).map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "Worker is not listening")
+            })?;
+        }
+
+        Ok(frames.len())
+    }
+
+    /// Reserves the run for a request unless another request is preparing it
+    /// or some of its images are still queued or processed. Checks and
+    /// reserves under one lock, so that two requests cannot both pass.
+    fn reserve(&self, name: &str, run: &str) -> Result<Reservation, SubmitError> {
+        let key = active_key(name, run);
+        let mut active = self.active.lock().expect("Active jobs are poisoned");
+        let busy = match active.get(&key) {
+            None => false,
+            Some(Slot::Reserved) => true,
+            Some(Slot::Queued(job)) => job.upgrade().is_some(),
+        };
+        if busy {
+            return Err(SubmitError::Conflict(format!(
+                "Run {} of {} is still being processed.",
+                run, name
+            )));
+        }
+
+        active.insert(key.clone(), Slot::Reserved);
+        Ok(Reservation {
+            active: &self.active,
+            key,
+        })
+    }
+}
+
+/// Record of a job which starts given run from scratch.
+fn new_record(
+    name: &str,
+    run: String,
+    settings: Option<Settings>,
+    frames: Vec<String>,
+) -> JobRecord {
+    JobRecord {
+        name: name.to_string(),
+        run,
+        created: now(),
+        settings: settings.unwrap_or_default(),
+        frames,
+    }
+}
+
+/// Current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn active_key(name: &str, run: &str) -> String {
+    format!("{}/{}", name, run)
+}
+
+/// File names of the images in given directory, sorted. Other files are
+/// skipped.
+fn list_frames(input_dir: &Path) -> io::Result<Vec<String>> {
+    let mut frames: Vec<String> = fs::read_dir(input_dir)?
+        .filter_map(|result| result.ok().map(|item| item.path()))
+        .filter(|item| item.is_file() && is_image(item))
+        .filter_map(|file| file.file_name()?.to_str().map(String::from))
+        .collect();
+    frames.sort();
+
+    Ok(frames)
+}
+
+/// Settings are compared by their serialized form, as they are persisted.
+fn same_settings(a: &Settings, b: &Settings) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::journal::{mark_complete, mark_failed};
+    use super::super::scratch::Scratch;
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Queue over a directory `dir` with three images and a file which is not
+    /// one. The tasks stay in the receiver, so their jobs are active until
+    /// they are drained.
+    fn queue(scratch: &Scratch) -> (Queue, Receiver<Task>) {
+        let input_dir = scratch.join("in/dir");
+        fs::create_dir_all(&input_dir).unwrap();
+        for frame in &["a.png", "b.png", "c.png", "notes.txt"] {
+            fs::write(input_dir.join(frame), b"").unwrap();
+        }
+
+        let (producer, consumer) = channel();
+        let queue = Queue::new(producer, scratch.join("in"), scratch.join("out"));
+        (queue, consumer)
+    }
+
+    fn frames(consumer: &Receiver<Task>) -> Vec<String> {
+        consumer
+            .try_iter()
+            .map(|task| {
+                task.image
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn requests_without_mode_create_runs() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+
+        let first = queue.submit("dir", None, None, None).unwrap();
+        let second = queue.submit("dir", None, None, None).unwrap();
+        assert_eq!((first.run.as_str(), first.frames), ("run_0001", 3));
+        assert_eq!((second.run.as_str(), second.frames), ("run_0002", 3));
+        assert_eq!(frames(&consumer).len(), 6);
+
+        match queue.submit("missing", None, None, None) {
+            Err(SubmitError::NotFound) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn resume_and_append_skip_complete_frames() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+        queue.submit("dir", None, None, None).unwrap();
+        frames(&consumer);
+
+        let run_dir = scratch.join("out/dir/run_0001");
+        mark_complete(&run_dir, "a.png").unwrap();
+        mark_failed(&run_dir, "b.png", "broken").unwrap();
+        fs::write(scratch.join("in/dir/d.png"), b"").unwrap();
+
+        // Failed frames are tried again when resumed explicitly, new ones only
+        // when appended.
+        let resumed = queue.submit("dir", None, Some(Mode::Resume), None).unwrap();
+        assert_eq!(resumed.run, "run_0001");
+        assert_eq!(frames(&consumer), vec!["b.png", "c.png"]);
+
+        let appended = queue
+            .submit("dir", None, Some(Mode::Append), Some("run_0001"))
+            .unwrap();
+        assert_eq!(appended.run, "run_0001");
+        assert_eq!(frames(&consumer), vec!["b.png", "c.png", "d.png"]);
+    }
+
+    #[test]
+    fn overwrite_starts_from_scratch() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+        queue.submit("dir", None, None, None).unwrap();
+        frames(&consumer);
+
+        let run_dir = scratch.join("out/dir/run_0001");
+        mark_complete(&run_dir, "a.png").unwrap();
+        let settings = Settings {
+            cell_size: 8,
+            ..Settings::default()
+        };
+
+        let overwritten = queue
+            .submit("dir", Some(settings), Some(Mode::Overwrite), None)
+            .unwrap();
+        assert_eq!(
+            (overwritten.run.as_str(), overwritten.frames),
+            ("run_0001", 3)
+        );
+        assert!(!is_complete(&run_dir, "a.png"));
+        assert_eq!(read_job(&run_dir).unwrap().unwrap().settings.cell_size, 8);
+
+        // Resuming with other settings than the run has conflicts.
+        frames(&consumer);
+        match queue.submit("dir", Some(Settings::default()), Some(Mode::Resume), None) {
+            Err(SubmitError::Conflict(_)) => {}
+            other => panic!("{:?}", other),
+        }
+
+        // Feedback is never removed.
+        fs::write(run_dir.join("feedback.jsonl"), b"").unwrap();
+        match queue.submit("dir", None, Some(Mode::Overwrite), None) {
+            Err(SubmitError::Conflict(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(run_dir.join("feedback.jsonl").is_file());
+        assert!(queue.submit("dir", None, None, None).is_ok());
+    }
+
+    #[test]
+    fn active_run_cannot_be_continued() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+        queue.submit("dir", None, None, None).unwrap();
+
+        match queue.submit("dir", None, Some(Mode::Overwrite), None) {
+            Err(SubmitError::Conflict(_)) => {}
+            other => panic!("{:?}", other),
+        }
+
+        frames(&consumer);
+        assert!(queue.submit("dir", None, Some(Mode::Resume), None).is_ok());
+    }
+
+    #[test]
+    fn reserved_run_cannot_be_reserved_again() {
+        let scratch = Scratch::new();
+        let (queue, _consumer) = queue(&scratch);
+
+        let reservation = queue.reserve("dir", "run_0001").unwrap();
+        assert!(queue.reserve("dir", "run_0001").is_err());
+        assert!(queue.reserve("dir", "run_0002").is_ok());
+
+        // A request which fails before queueing releases the run.
+        drop(reservation);
+        assert!(queue.reserve("dir", "run_0001").is_ok());
+    }
+
+    #[test]
+    fn replay_queues_unfinished_frames() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+        queue.submit("dir", None, None, None).unwrap();
+        queue.submit("dir", None, None, None).unwrap();
+        frames(&consumer);
+
+        let run_dir = scratch.join("out/dir/run_0001");
+        mark_complete(&run_dir, "a.png").unwrap();
+        mark_failed(&run_dir, "b.png", "broken").unwrap();
+        mark_complete(&scratch.join("out/dir/run_0002"), "c.png").unwrap();
+
+        // A restarted service does not try failed frames again.
+        let (queue, consumer) = self::queue(&scratch);
+        queue.replay();
+        assert_eq!(frames(&consumer), vec!["c.png", "a.png", "b.png"]);
+    }
+
+    #[test]
+    fn every_run_is_a_pass() {
+        let scratch = Scratch::new();
+        let (queue, _consumer) = queue(&scratch);
+        queue.submit("dir", None, None, None).unwrap();
+
+        let pass = queue.rewatch("dir", &[14, 12]).unwrap();
+        assert_eq!((pass.pass, pass.run.as_str()), (2, "run_0002"));
+        assert_eq!((pass.cell_size, pass.frames), (12, 3));
+
+        let pass = queue.rewatch("dir", &[14, 12]).unwrap();
+        assert_eq!((pass.pass, pass.cell_size), (3, 12));
+
+        // Another request created a run, but has not recorded it yet.
+        let output_dir = scratch.join("out/dir");
+        assert_eq!(create_run(&output_dir).unwrap(), "run_0004");
+        let pass = queue.rewatch("dir", &[14, 12, 12, 12, 10]).unwrap();
+        assert_eq!((pass.pass, pass.run.as_str()), (5, "run_0005"));
+        assert_eq!(pass.cell_size, 10);
+
+        let passes: Vec<String> = read_watch(&output_dir)
+            .unwrap()
+            .passes
+            .into_iter()
+            .map(|pass| pass.run)
+            .collect();
+        assert_eq!(passes, vec!["run_0002", "run_0003", "run_0005"]);
+        let record = read_job(&output_dir.join("run_0003")).unwrap().unwrap();
+        assert_eq!(record.settings.cell_size, 12);
+    }
+
+    #[test]
+    fn pass_which_cannot_be_recorded_is_not_queued() {
+        let scratch = Scratch::new();
+        let (queue, consumer) = queue(&scratch);
+        // The record of the passes cannot be replaced by a file.
+        fs::create_dir_all(scratch.join("out/dir/watch.json/blocked")).unwrap();
+
+        assert!(queue.rewatch("dir", &[14]).is_err());
+        assert!(frames(&consumer).is_empty());
+        assert!(!scratch.join("out/dir/run_0001").exists());
+    }
+}
@@ -0,0 +1,500 @@
+use super::helpers::is_image;
+use super::journal::{is_complete, is_failed, mark_complete, read_job, record_job, JobRecord};
+use super::metadata::read_frames;
+use super::settings::Settings;
+use super::sweep::{DEFAULTS, SWEEP_REPORT};
+use super::watch::WATCH;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Run the highlights of directories processed before there were runs are
+/// moved into.
+const BASELINE_RUN: &str = "run_0001";
+
+/// Overview of a run of a directory.
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub run: String,
+
+    /// When the run was created, in seconds since the Unix epoch.
+    pub created: u64,
+
+    /// All parameters of the pipeline the run was processed with.
+    pub settings: Settings,
+
+    /// Number of images requested in the run.
+    pub requested: usize,
+
+    /// Number of frames which are processed.
+    pub frames: usize,
+
+    /// Number of highlights in those frames.
+    pub highlights: usize,
+
+    /// Number of frames which could not be processed.
+    pub failed: usize,
+}
+
+/// Number of highlights of each frame in two runs of the same directory.
+#[derive(Serialize)]
+pub struct RunDiff {
+    pub a: String,
+    pub b: String,
+
+    /// Total number of highlights in each run.
+    pub highlights: (usize, usize),
+
+    /// Frames which are processed in at least one of the runs, sorted by name.
+    pub frames: Vec<FrameDiff>,
+}
+
+#[derive(Serialize)]
+pub struct FrameDiff {
+    /// File name of the original image.
+    pub frame: String,
+
+    /// Number of highlights in each run, if the frame is processed in it.
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+}
+
+/// Lists the runs in the output directory of a request, oldest first.
+pub fn list_runs(output_dir: &Path) -> io::Result<Vec<String>> {
+    let mut runs: Vec<String> = Vec::new();
+    if !output_dir.is_dir() {
+        return Ok(runs);
+    }
+
+    for entry in fs::read_dir(output_dir)? {
+        if let Some(record) = read_job(&entry?.path())? {
+            runs.push(record.run);
+        }
+    }
+
+    runs.sort();
+    Ok(runs)
+}
+
+/// The most recent run in the output directory of a request, if there is one.
+pub fn latest_run(output_dir: &Path) -> io::Result<Option<String>> {
+    Ok(list_runs(output_dir)?.pop())
+}
+
+/// Creates the directory of a new run in the output directory of a request and
+/// returns its identifier. Identifiers are numbered so that they sort by age.
+pub fn create_run(output_dir: &Path) -> io::Result<String> {
+    fs::create_dir_all(output_dir)?;
+
+    let mut number = fs::read_dir(output_dir)?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|name| run_number(&name))
+        .max()
+        .unwrap_or(0);
+
+    // Another request might have created the same run in the meantime.
+    loop {
+        number += 1;
+        let run = format!("run_{:04}", number);
+        match fs::create_dir(output_dir.join(&run)) {
+            Ok(()) => return Ok(run),
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Number of given run, which counts the runs of the directory up to it.
+pub fn run_number(run: &str) -> Option<usize> {
+    run.trim_start_matches("run_").parse().ok()
+}
+
+/// Summarizes all runs in the output directory of a request, oldest first.
+pub fn summarize_runs(output_dir: &Path) -> io::Result<Vec<RunSummary>> {
+    let mut summaries: Vec<RunSummary> = Vec::new();
+
+    for run in list_runs(output_dir)? {
+        let run_dir = output_dir.join(&run);
+        let record = match read_job(&run_dir)? {
+            None => continue,
+            Some(record) => record,
+        };
+
+        let frames = read_frames(&run_dir)?;
+        let requested = record.frames.len();
+        summaries.push(RunSummary {
+            run,
+            created: record.created,
+            settings: record.settings,
+            requested,
+            frames: record
+                .frames
+                .iter()
+                .filter(|frame| is_complete(&run_dir, frame))
+                .count(),
+            highlights: frames.iter().map(|frame| frame.highlights.len()).sum(),
+            failed: record
+                .frames
+                .iter()
+                .filter(|frame| is_failed(&run_dir, frame))
+                .count(),
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// Compares the number of highlights of each frame in two runs in the output
+/// directory of a request.
+pub fn diff_runs(output_dir: &Path, a: &str, b: &str) -> io::Result<RunDiff> {
+    let mut counts: BTreeMap<String, (Option<usize>, Option<usize>)> = BTreeMap::new();
+
+    for frame in read_frames(&output_dir.join(a))? {
+        counts.entry(frame.frame).or_default().0 = Some(frame.highlights.len());
+    }
+    for frame in read_frames(&output_dir.join(b))? {
+        counts.entry(frame.frame).or_default().1 = Some(frame.highlights.len());
+    }
+
+    let highlights = counts.values().fold((0, 0), |(total_a, total_b), (a, b)| {
+        (total_a + a.unwrap_or(0), total_b + b.unwrap_or(0))
+    });
+
+    Ok(RunDiff {
+        a: a.to_string(),
+        b: b.to_string(),
+        highlights,
+        frames: counts
+            .into_iter()
+            .map(|(frame, (a, b))| FrameDiff { frame, a, b })
+            .collect(),
+    })
+}
+
+/// Moves the highlights of the directories in the output root which were
+/// processed before there were runs into their first run, so that they are
+/// listed and continued like any other. Directories which hold anything else
+/// than such highlights are left as they are. Returns the names of the
+/// migrated directories.
+pub fn migrate_baseline(input_root: &Path, output_root: &Path) -> Vec<String> {
+    let mut migrated: Vec<String> = Vec::new();
+
+    let entries = match fs::read_dir(output_root) {
+        Ok(entries) => entries,
+        Err(_) => return migrated,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let output_dir = entry.path();
+        let name = match output_dir.file_name().and_then(|name| name.to_str()) {
+            Some(name) if output_dir.is_dir() => name.to_string(),
+            _ => continue,
+        };
+
+        match migrate_directory(&input_root.join(&name), &output_dir, &name) {
+            Ok(true) => migrated.push(name),
+            Ok(false) => {}
+            Err(error) => println!("Cannot move the highlights of {}: {}.", name, error),
+        }
+    }
+
+    migrated.sort();
+    migrated
+}
+
+/// Moves the highlights of a single directory into its first run if all its
+/// files are highlights named `{stem}_{index}.png`, as the service wrote them
+/// before there were runs. Frames with highlights are complete, as those were
+/// written once the frame was processed. A migration which was interrupted
+/// continues where it stopped, as the record of the run is written last.
+fn migrate_directory(input_dir: &Path, output_dir: &Path, name: &str) -> io::Result<bool> {
+    if !list_runs(output_dir)?.is_empty() {
+        return Ok(false);
+    }
+
+    let run_dir = output_dir.join(BASELINE_RUN);
+    let mut loose: Vec<String> = Vec::new();
+    for entry in fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name.to_string(),
+            None => return Ok(false),
+        };
+
+        // Files which belong to the directory rather than to a run stay.
+        if path.is_file() && [DEFAULTS, SWEEP_REPORT, WATCH].contains(&file_name.as_str()) {
+            continue;
+        }
+        if path == run_dir && path.is_dir() {
+            continue;
+        }
+        if !path.is_file() || baseline_stem(&file_name).is_none() {
+            return Ok(false);
+        }
+        loose.push(file_name);
+    }
+
+    // Highlights which an interrupted migration already moved.
+    let mut moved: Vec<String> = Vec::new();
+    if run_dir.is_dir() {
+        for entry in fs::read_dir(&run_dir)? {
+            let path = entry?.path();
+            match path.file_name().and_then(|name| name.to_str()) {
+                Some(file_name) if path.is_file() && baseline_stem(file_name).is_some() => {
+                    moved.push(file_name.to_string())
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    if loose.is_empty() && moved.is_empty() {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(&run_dir)?;
+    for file_name in loose {
+        fs::rename(output_dir.join(&file_name), run_dir.join(&file_name))?;
+        moved.push(file_name);
+    }
+
+    let stems: HashSet<&str> = moved
+        .iter()
+        .filter_map(|file| baseline_stem(file))
+        .collect();
+    let mut frames: Vec<String> = Vec::new();
+    if input_dir.is_dir() {
+        for entry in fs::read_dir(input_dir)? {
+            let path = entry?.path();
+            let stem = path.file_stem().and_then(|stem| stem.to_str());
+            if is_image(&path) && stem.map_or(false, |stem| stems.contains(stem)) {
+                if let Some(frame) = path.file_name().and_then(|name| name.to_str()) {
+                    frames.push(frame.to_string());
+                }
+            }
+        }
+    }
+    frames.sort();
+
+    for frame in frames.iter() {
+        mark_complete(&run_dir, frame)?;
+    }
+
+    let created = moved
+        .iter()
+        .map(|file| created(&run_dir.join(file)))
+        .min()
+        .unwrap_or(0);
+    record_job(
+        &run_dir,
+        &JobRecord {
+            name: name.to_string(),
+            run: BASELINE_RUN.to_string(),
+            created,
+            settings: Settings::default(),
+            frames,
+        },
+    )?;
+
+    Ok(true)
+}
+
+/// Stem of the frame of a highlight the service wrote before there were runs,
+/// if the file name is one of those.
+fn baseline_stem(file_name: &str) -> Option<&str> {
+    let (stem, index) = file_name.strip_suffix(".png")?.rsplit_once('_')?;
+    if stem.is_empty() || index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(stem)
+}
+
+/// When the file was last modified, in seconds since the Unix epoch.
+fn created(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::Rect;
+    use super::super::journal::JOB_RECORD;
+    use super::super::metadata::{frame_with_rects, metadata_path};
+    use super::super::scratch::Scratch;
+    use super::*;
+    use std::fs::File;
+
+    fn rects(count: u32) -> Vec<Rect> {
+        (0..count)
+            .map(|i| Rect {
+                x: 10 * i,
+                y: 0,
+                width: 5,
+                height: 5,
+            })
+            .collect()
+    }
+
+    fn write_json<T: Serialize>(path: &Path, value: &T) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        serde_json::to_writer(File::create(path).unwrap(), value).unwrap();
+    }
+
+    fn record(name: &str, run: &str, frames: &[&str]) -> JobRecord {
+        JobRecord {
+            name: name.to_string(),
+            run: run.to_string(),
+            created: 0,
+            settings: Settings::default(),
+            frames: frames.iter().map(|frame| frame.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn runs_are_numbered_after_the_latest() {
+        let scratch = Scratch::new();
+        let output_dir = scratch.join("dir");
+
+        assert_eq!(create_run(&output_dir).unwrap(), "run_0001");
+        assert_eq!(create_run(&output_dir).unwrap(), "run_0002");
+
+        // Gaps are not filled and other entries are not runs.
+        fs::create_dir(output_dir.join("run_0007")).unwrap();
+        fs::create_dir(output_dir.join("export")).unwrap();
+        fs::write(output_dir.join(DEFAULTS), b"{}").unwrap();
+        assert_eq!(create_run(&output_dir).unwrap(), "run_0008");
+
+        // Only runs with a record are listed.
+        record_job(
+            &output_dir.join("run_0002"),
+            &record("dir", "run_0002", &[]),
+        )
+        .unwrap();
+        record_job(
+            &output_dir.join("run_0001"),
+            &record("dir", "run_0001", &[]),
+        )
+        .unwrap();
+        assert_eq!(
+            list_runs(&output_dir).unwrap(),
+            vec!["run_0001", "run_0002"]
+        );
+        assert_eq!(
+            latest_run(&output_dir).unwrap(),
+            Some("run_0002".to_string())
+        );
+    }
+
+    #[test]
+    fn diff_counts_highlights_of_each_frame() {
+        let scratch = Scratch::new();
+        let (a, b) = (scratch.join("run_0001"), scratch.join("run_0002"));
+        write_json(
+            &metadata_path(&a, "1"),
+            &frame_with_rects("1.png", &rects(2)),
+        );
+        write_json(
+            &metadata_path(&a, "2"),
+            &frame_with_rects("2.png", &rects(3)),
+        );
+        write_json(
+            &metadata_path(&b, "2"),
+            &frame_with_rects("2.png", &rects(1)),
+        );
+        write_json(
+            &metadata_path(&b, "3"),
+            &frame_with_rects("3.png", &rects(0)),
+        );
+
+        let diff = diff_runs(&scratch, "run_0001", "run_0002").unwrap();
+        assert_eq!(diff.highlights, (5, 1));
+        let frames: Vec<(&str, Option<usize>, Option<usize>)> = diff
+            .frames
+            .iter()
+            .map(|frame| (frame.frame.as_str(), frame.a, frame.b))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                ("1.png", Some(2), None),
+                ("2.png", Some(3), Some(1)),
+                ("3.png", None, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn baseline_directories_become_first_runs() {
+        let scratch = Scratch::new();
+        let (input_root, output_root) = (scratch.join("in"), scratch.join("out"));
+        fs::create_dir_all(input_root.join("old")).unwrap();
+        for frame in &["a_b.png", "c.jpg", "d.png", "notes.txt"] {
+            fs::write(input_root.join("old").join(frame), b"").unwrap();
+        }
+
+        // Highlights of two of the frames and the defaults of a sweep.
+        let old = output_root.join("old");
+        fs::create_dir_all(&old).unwrap();
+        for file in &["a_b_0.png", "a_b_1.png", "c_0.png"] {
+            fs::write(old.join(file), b"").unwrap();
+        }
+        fs::write(old.join(DEFAULTS), b"{}").unwrap();
+
+        // Interrupted in the middle of the migration.
+        let interrupted = output_root.join("interrupted");
+        fs::create_dir_all(interrupted.join(BASELINE_RUN)).unwrap();
+        fs::write(interrupted.join("1_0.png"), b"").unwrap();
+        fs::write(interrupted.join(BASELINE_RUN).join("1_1.png"), b"").unwrap();
+
+        // A run which was created, but not recorded yet.
+        let unrecorded = output_root.join("unrecorded");
+        fs::create_dir_all(unrecorded.join(BASELINE_RUN).join("frames")).unwrap();
+
+        // Other files than highlights, or a run already.
+        let other = output_root.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("1_0.png"), b"").unwrap();
+        fs::write(other.join("1.json"), b"{}").unwrap();
+        let current = output_root.join("current");
+        fs::create_dir_all(current.join("run_0001")).unwrap();
+        fs::write(current.join("1_0.png"), b"").unwrap();
+        record_job(
+            &current.join("run_0001"),
+            &record("current", "run_0001", &[]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            migrate_baseline(&input_root, &output_root),
+            vec!["interrupted", "old"]
+        );
+        assert!(migrate_baseline(&input_root, &output_root).is_empty());
+
+        let run = old.join(BASELINE_RUN);
+        assert_eq!(list_runs(&old).unwrap(), vec![BASELINE_RUN]);
+        assert!(old.join(DEFAULTS).is_file());
+        assert!(run.join("a_b_1.png").is_file() && !old.join("a_b_1.png").exists());
+        let record = read_job(&run).unwrap().unwrap();
+        assert_eq!(
+            (record.name.as_str(), record.run.as_str()),
+            ("old", BASELINE_RUN)
+        );
+        assert_eq!(record.frames, vec!["a_b.png", "c.jpg"]);
+        assert!(is_complete(&run, "a_b.png") && is_complete(&run, "c.jpg"));
+
+        let run = interrupted.join(BASELINE_RUN);
+        assert!(run.join("1_0.png").is_file() && run.join("1_1.png").is_file());
+        assert!(read_job(&run).unwrap().unwrap().frames.is_empty());
+
+        assert!(!unrecorded.join(BASELINE_RUN).join(JOB_RECORD).exists());
+        assert!(other.join("1_0.png").is_file() && !other.join(BASELINE_RUN).exists());
+        assert!(current.join("1_0.png").is_file());
+    }
+}
@@ -0,0 +1,168 @@
+use super::adaptive::Adaptive;
+use super::cut_highlights_from_image::Cropping;
+use super::encoding::Encoding;
+use super::extract_highlights::MAX_CELLS;
+use super::heat_map::{validate_cell_size, CELL_SIZE};
+use super::naming::Naming;
+use super::post_processing::PostProcessing;
+use super::pyramid::Pyramid;
+use super::shards::Sharding;
+use serde::{Deserialize, Serialize};
+
+/// Parameters of the pipeline which can be tweaked per request. Each field has
+/// a default that reproduces the original behaviour of the service, therefore
+/// the request only has to list what it wants changed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Size of the cells of the bricked heat map in pixels. Cells of the final
+    /// heat map are half of this size.
+    pub cell_size: u32,
+
+    /// Searches the cell size for each frame, starting at the one above, until
+    /// its highlights fall within the configured bands.
+    pub adaptive: Option<Adaptive>,
+
+    /// How the objects which are larger than the maximum number of cells are
+    /// divided into smaller ones.
+    pub split: SplitStrategy,
+
+    /// Objects which span this many cells of the heat map or more in either
+    /// direction are split.
+    pub max_cells: u32,
+
+    /// Which objects are merged, suppressed or filtered out after extraction.
+    pub post_processing: PostProcessing,
+
+    /// Also runs the pipeline at each cell size of the pyramid and links the
+    /// highlights across them.
+    pub pyramid: Option<Pyramid>,
+
+    /// How the highlights are cut out of the original image.
+    pub crop: Cropping,
+
+    /// Format in which the highlights are persisted.
+    pub encoding: Encoding,
+
+    /// Where the highlights are persisted within the directory of the run.
+    pub naming: Naming,
+
+    /// Packs the highlights into tar shards instead of separate files.
+    pub shards: Option<Sharding>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            cell_size: CELL_SIZE,
+            adaptive: None,
+            split: SplitStrategy::default(),
+            max_cells: MAX_CELLS,
+            post_processing: PostProcessing::default(),
+            pyramid: None,
+            crop: Cropping::default(),
+            encoding: Encoding::default(),
+            naming: Naming::default(),
+            shards: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Smallest cell size the pipeline can run with, given the adaptive
+    /// search and the pyramid.
+    pub fn smallest_cell_size(&self) -> u32 {
+        let cell_size = self
+            .adaptive
+            .as_ref()
+            .map_or(self.cell_size, |adaptive| adaptive.min_cell_size);
+        self.pyramid
+            .iter()
+            .flat_map(|pyramid| pyramid.cell_sizes.iter().cloned())
+            .fold(cell_size, u32::min)
+    }
+
+    /// Checks the values which would make the pipeline fail. Returns the reason
+    /// why the settings are invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_cell_size(self.cell_size)?;
+
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.validate()?;
+        }
+
+        if let Some(pyramid) = &self.pyramid {
+            pyramid.validate()?;
+        }
+
+        if self.max_cells == 0 {
+            return Err("Maximum number of cells cannot be zero.".to_string());
+        }
+
+        self.crop.validate()?;
+
+        if self.encoding.jpeg_quality == 0 || self.encoding.jpeg_quality > 100 {
+            return Err("JPEG quality has to be between 1 and 100.".to_string());
+        }
+
+        if let Some(shards) = &self.shards {
+            if shards.shard_size == 0 {
+                return Err("Shard size cannot be zero.".to_string());
+            }
+        }
+
+        self.naming.validate()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitStrategy {
+    /// Erodes the object by one cell and flood fills it again until all of its
+    /// parts fit the limit. Thin parts of the object disappear in the process.
+    #[default]
+    Peel,
+    /// Divides the object along its narrowest necks found by a distance
+    /// transform. Every cell of the object is kept in one of the children.
+    Watershed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cut_highlights_from_image::{MAX_DILATE, MAX_FEATHER, MAX_PADDING};
+    use super::*;
+
+    fn validate(change: impl FnOnce(&mut Settings)) -> Result<(), String> {
+        let mut settings = Settings::default();
+        change(&mut settings);
+        settings.validate()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(validate(|_| ()).is_ok());
+    }
+
+    #[test]
+    fn jpeg_quality_is_bounded() {
+        assert!(validate(|s| s.encoding.jpeg_quality = 0).is_err());
+        assert!(validate(|s| s.encoding.jpeg_quality = 100).is_ok());
+        assert!(validate(|s| s.encoding.jpeg_quality = 101).is_err());
+    }
+
+    #[test]
+    fn padding_is_bounded() {
+        assert!(validate(|s| s.crop.padding = MAX_PADDING).is_ok());
+        assert!(validate(|s| s.crop.padding = MAX_PADDING + 1).is_err());
+        assert!(validate(|s| s.crop.padding = u32::MAX).is_err());
+    }
+
+    #[test]
+    fn mask_growth_and_blur_are_bounded() {
+        assert!(validate(|s| s.crop.dilate = MAX_DILATE).is_ok());
+        assert!(validate(|s| s.crop.dilate = MAX_DILATE + 1).is_err());
+        assert!(validate(|s| s.crop.feather = MAX_FEATHER).is_ok());
+        assert!(validate(|s| s.crop.feather = MAX_FEATHER + 1).is_err());
+        assert!(validate(|s| s.crop.feather = u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use super::point::Point;
+use super::visual_object::VisualObject;
+use serde::{Deserialize, Serialize};
+
+/// Directions in which the contour is traced, in clockwise order. Because the y
+/// axis points down, turning right means moving to the next direction.
+const DIRECTIONS: [(i64, i64); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+/// Describes the shape of an object. Positions are in cells of the heat map,
+/// where the cell at `x;y` spans from `x` to `x + 1` horizontally and from `y`
+/// to `y + 1` vertically.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShapeDescriptors {
+    /// Number of cells the object consists of.
+    pub area: u32,
+
+    /// Length in cell edges of the traced outer contour of the object. An
+    /// object merged from separate parts has the lengths of the contours of
+    /// all of them. Edges around holes do not count.
+    pub perimeter: u32,
+
+    /// `4π · area / perimeter²`. Steps along the cells keep the contour of a
+    /// disc as long as its bounding square, so a disc has about `π / 4`, as
+    /// does a square.
+    pub circularity: f64,
+
+    /// Mean position of the centres of the cells.
+    pub centroid: (f64, f64),
+
+    /// Angle of the major axis of the object to the x axis in radians.
+    pub orientation: f64,
+
+    /// 0 for a circular object, approaching 1 for an elongated one.
+    pub eccentricity: f64,
+
+    /// Corners of the smallest convex polygon containing the whole object, in
+    /// clockwise order.
+    pub convex_hull: Vec<Point>,
+
+    /// Ratio of the area of the object to the area of its convex hull.
+    pub solidity: f64,
+
+    /// Ratio of the area of the object to the area of its bounding rectangle.
+    pub extent: f64,
+
+    /// Seven moments which are invariant to translation, scale and rotation.
+    pub hu_moments: [f64; 7],
+}
+
+/// Computes descriptors of the shape of given object from its points.
+pub fn shape_descriptors(object: &mut VisualObject) -> Option<ShapeDescriptors> {
+    let (lower, higher) = object.bounds()?;
+    let perimeter = perimeter(&object.point_map()?);
+    let points: Vec<Point> = object
+        .points
+        .iter()
+        .map(|point| *point + object.reference)
+        .collect();
+
+    let area = points.len() as f64;
+    let (cx, cy) = (
+        points.iter().map(|p| p.x as f64 + 0.5).sum::<f64>() / area,
+        points.iter().map(|p| p.y as f64 + 0.5).sum::<f64>() / area,
+    );
+
+    // Central moment of given order.
+    let mu = |p: i32, q: i32| -> f64 {
+        points
+            .iter()
+            .map(|point| (point.x as f64 + 0.5 - cx).powi(p) * (point.y as f64 + 0.5 - cy).powi(q))
+            .sum()
+    };
+    let (mu20, mu02, mu11) = (mu(2, 0), mu(0, 2), mu(1, 1));
+
+    // The eigenvalues of the covariance matrix give the lengths of the axes.
+    let common = (4_f64 * mu11.powi(2) + (mu20 - mu02).powi(2)).sqrt();
+    let major = (mu20 + mu02 + common) / 2_f64;
+    let minor = (mu20 + mu02 - common) / 2_f64;
+    let eccentricity = if major > 0_f64 {
+        (1_f64 - minor / major).max(0_f64).sqrt()
+    } else {
+        0_f64
+    };
+
+    let convex_hull = convex_hull(&points);
+    let hull_area = polygon_area(&convex_hull);
+    let bounding_area = ((higher.x - lower.x + 1) * (higher.y - lower.y + 1)) as f64;
+
+    Some(ShapeDescriptors {
+        area: points.len() as u32,
+        perimeter,
+        circularity: 4_f64 * std::f64::consts::PI * area / (perimeter as f64).powi(2),
+        centroid: (cx, cy),
+        orientation: 0.5_f64 * (2_f64 * mu11).atan2(mu20 - mu02),
+        eccentricity,
+        convex_hull,
+        solidity: if hull_area > 0_f64 {
+            area / hull_area
+        } else {
+            1_f64
+        },
+        extent: area / bounding_area,
+        hu_moments: hu_moments(&mu, area),
+    })
+}
+
+/// Traces the outer contour of the object along the edges of its cells. The
+/// object is kept on the right side, therefore the contour runs clockwise. Cells
+/// which touch only by their corners are treated as connected, the same way the
+/// flood fill treats them.
+///
+/// Returns the corners of the contour. Only the part of the object connected to
+/// its top left cell is traced. Objects merged by the post processing can
+/// consist of parts which do not touch, the contour then leaves out the others.
+pub fn trace_contour(object: &mut VisualObject) -> Option<Vec<Point>> {
+    let map = object.point_map()?;
+    let (lower, _) = object.bounds()?;
+    let start_x = map.first()?.iter().position(|on| *on)?;
+
+    let (corners, _) = trace(&map, (start_x, 0));
+    Some(
+        corners
+            .into_iter()
+            .map(|(x, y)| lower + Point::new(x as u32, y as u32))
+            .collect(),
+    )
+}
+
+/// Traces the outer contour of the part of the map which contains given cell,
+/// starting at its top left corner. No other cell of the part can be above the
+/// cell or on its left in the same row. Returns the corners of the contour and
+/// its length in cell edges.
+fn trace(map: &[Vec<bool>], (start_x, start_y): (usize, usize)) -> (Vec<(i64, i64)>, u32) {
+    let is_on = |x: i64, y: i64| {
+        x >= 0 && y >= 0 && map.get(y as usize).and_then(|row| row.get(x as usize)) == Some(&true)
+    };
+
+    // There is an edge leaving given vertex in given direction if the cell on
+    // its right side is part of the object and the one on its left side is not.
+    let has_edge = |(x, y): (i64, i64), direction: usize| {
+        let (right, left) = match direction {
+            0 => ((x, y), (x, y - 1)),
+            1 => ((x - 1, y), (x, y)),
+            2 => ((x - 1, y - 1), (x - 1, y)),
+            _ => ((x, y - 1), (x - 1, y - 1)),
+        };
+
+        is_on(right.0, right.1) && !is_on(left.0, left.1)
+    };
+
+    // The top edge of the cell is always on the contour.
+    let start = ((start_x as i64, start_y as i64), 0);
+    let limit = 4 * map.len() * map[0].len();
+
+    let mut corners: Vec<(i64, i64)> = Vec::new();
+    let (mut vertex, mut direction) = start;
+    let mut length = 0;
+
+    loop {
+        let (dx, dy) = DIRECTIONS[direction];
+        vertex = (vertex.0 + dx, vertex.1 + dy);
+        length += 1;
+
+        // Prefers turning left so that cells touching by corners stay connected.
+        let next = [3, 0, 1]
+            .iter()
+            .map(|turn| (direction + turn) % 4)
+            .find(|next| has_edge(vertex, *next))
+            .expect("Contour of an object cannot end");
+
+        if next != direction {
+            corners.push(vertex);
+        }
+
+        direction = next;
+        if (vertex, direction) == start || length > limit {
+            break;
+        }
+    }
+
+    (corners, length as u32)
+}
+
+/// Sums the lengths of the outer contours of the parts of the map, which are
+/// the cells connected by their edges or corners.
+fn perimeter(map: &[Vec<bool>]) -> u32 {
+    let mut traced: Vec<Vec<bool>> = map.iter().map(|row| vec![false; row.len()]).collect();
+    let mut perimeter = 0;
+
+    for (y, row) in map.iter().enumerate() {
+        for (x, on) in row.iter().enumerate() {
+            if !*on || traced[y][x] {
+                continue;
+            }
+
+            // Cells are visited row by row, so the first cell of a part has no
+            // other one above it or on its left.
+            perimeter += trace(map, (x, y)).1;
+
+            // Marks the whole part, so that it is traced once.
+            traced[y][x] = true;
+            let mut stack: Vec<(usize, usize)> = vec![(x, y)];
+            while let Some((x, y)) = stack.pop() {
+                for ny in y.saturating_sub(1)..=(y + 1).min(map.len() - 1) {
+                    for nx in x.saturating_sub(1)..=(x + 1).min(map[ny].len() - 1) {
+                        if map[ny][nx] && !traced[ny][nx] {
+                            traced[ny][nx] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    perimeter
+}
+
+/// Finds the convex hull of the corners of given cells with the monotone chain
+/// algorithm. The hull is returned in clockwise order.
+fn convex_hull(points: &[Point]) -> Vec<Point> {
+    let mut corners: Vec<(i64, i64)> = points
+        .iter()
+        .flat_map(|p| {
+            let (x, y) = (p.x as i64, p.y as i64);
+            vec![(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+        })
+        .collect();
+    corners.sort();
+    corners.dedup();
+
+    // The lower chain runs through the corners from left to right and the upper
+    // one back. The last corner of each chain is the first one of the other.
+    let mut hull = monotone_chain(corners.iter());
+    hull.pop();
+    let mut upper = monotone_chain(corners.iter().rev());
+    upper.pop();
+    hull.append(&mut upper);
+
+    hull.into_iter()
+        .map(|(x, y)| Point::new(x as u32, y as u32))
+        .collect()
+}
+
+/// Keeps only those corners which make a turn in the same direction.
+fn monotone_chain<'a, I>(corners: I) -> Vec<(i64, i64)>
+where
+    I: Iterator<Item = &'a (i64, i64)>,
+{
+    let cross = |o: (i64, i64), a: (i64, i64), b: (i64, i64)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+
+    let mut chain: Vec<(i64, i64)> = Vec::new();
+    for corner in corners {
+        while chain.len() >= 2
+            && cross(chain[chain.len() - 2], chain[chain.len() - 1], *corner) <= 0
+        {
+            chain.pop();
+        }
+        chain.push(*corner);
+    }
+
+    chain
+}
+
+/// Area of a simple polygon calculated with the shoelace formula.
+pub fn polygon_area(polygon: &[Point]) -> f64 {
+    let doubled: i64 = (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64
+        })
+        .sum();
+
+    doubled.abs() as f64 / 2_f64
+}
+
+/// Calculates the Hu moments from the central moments of the object.
+fn hu_moments<F>(mu: &F, area: f64) -> [f64; 7]
+where
+    F: Fn(i32, i32) -> f64,
+{
+    // Normalized central moment which is invariant to scale.
+    let eta = |p: i32, q: i32| mu(p, q) / area.powf(1_f64 + (p + q) as f64 / 2_f64);
+    let (n20, n02, n11) = (eta(2, 0), eta(0, 2), eta(1, 1));
+    let (n30, n03, n21, n12) = (eta(3, 0), eta(0, 3), eta(2, 1), eta(1, 2));
+
+    let (a, b) = (n30 + n12, n21 + n03);
+    let (c, d) = (n30 - 3_f64 * n12, 3_f64 * n21 - n03);
+
+    [
+        n20 + n02,
+        (n20 - n02).powi(2) + 4_f64 * n11.powi(2),
+        c.powi(2) + d.powi(2),
+        a.powi(2) + b.powi(2),
+        c * a * (a.powi(2) - 3_f64 * b.powi(2)) + d * b * (3_f64 * a.powi(2) - b.powi(2)),
+        (n20 - n02) * (a.powi(2) - b.powi(2)) + 4_f64 * n11 * a * b,
+        d * a * (a.powi(2) - 3_f64 * b.powi(2)) - c * b * (3_f64 * a.powi(2) - b.powi(2)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(reference: Point, cells: &[(u32, u32)]) -> VisualObject {
+        let mut object = VisualObject::new(reference);
+        for (x, y) in cells {
+            object.push(Point::new(*x, *y));
+        }
+        object
+    }
+
+    fn rectangle(width: u32, height: u32) -> Vec<(u32, u32)> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect()
+    }
+
+    /// Cells whose centres are within the radius from the centre of the disc.
+    fn disc(radius: f64) -> Vec<(u32, u32)> {
+        let size = (2.0 * radius).ceil() as u32;
+        rectangle(size, size)
+            .into_iter()
+            .filter(|(x, y)| {
+                let (dx, dy) = (*x as f64 + 0.5 - radius, *y as f64 + 0.5 - radius);
+                dx * dx + dy * dy <= radius * radius
+            })
+            .collect()
+    }
+
+    fn points(corners: &[(u32, u32)]) -> Vec<Point> {
+        corners.iter().map(|(x, y)| Point::new(*x, *y)).collect()
+    }
+
+    #[test]
+    fn rectangle_descriptors() {
+        let mut rect = object(Point::new(0, 0), &rectangle(4, 2));
+        let shape = shape_descriptors(&mut rect).unwrap();
+
+        assert_eq!(shape.area, 8);
+        assert_eq!(shape.perimeter, 12);
+        assert_eq!(shape.centroid, (2.0, 1.0));
+        assert_eq!(shape.orientation, 0.0);
+        assert_eq!(shape.convex_hull, points(&[(0, 0), (4, 0), (4, 2), (0, 2)]));
+        assert_eq!((shape.solidity, shape.extent), (1.0, 1.0));
+        // Variances along the axes are 15/12 and 3/12 per cell.
+        assert!((shape.eccentricity - (1.0 - 3.0 / 15.0_f64).sqrt()).abs() < 1e-9);
+        assert_eq!(
+            trace_contour(&mut rect).unwrap(),
+            points(&[(4, 0), (4, 2), (0, 2), (0, 0)])
+        );
+
+        let mut square = object(Point::new(0, 0), &rectangle(3, 3));
+        assert_eq!(shape_descriptors(&mut square).unwrap().eccentricity, 0.0);
+    }
+
+    #[test]
+    fn l_shape_descriptors() {
+        // Three cells tall and three wide, one cell thick.
+        let cells = [(0, 0), (0, 1), (0, 2), (1, 2), (2, 2)];
+        let shape = shape_descriptors(&mut object(Point::new(0, 0), &cells)).unwrap();
+
+        assert_eq!(shape.area, 5);
+        assert_eq!(shape.perimeter, 12);
+        assert_eq!(
+            shape.convex_hull,
+            points(&[(0, 0), (1, 0), (3, 2), (3, 3), (0, 3)])
+        );
+        assert_eq!(shape.solidity, 5.0 / 7.0);
+        assert_eq!(shape.extent, 5.0 / 9.0);
+        // The shape is symmetric along the diagonal going down to the right.
+        assert!((shape.orientation - std::f64::consts::FRAC_PI_4).abs() < 1e-9);
+        assert!(shape.eccentricity > 0.5);
+    }
+
+    #[test]
+    fn disc_descriptors() {
+        let cells = disc(10.0);
+        let shape = shape_descriptors(&mut object(Point::new(0, 0), &cells)).unwrap();
+
+        assert_eq!(shape.area as usize, cells.len());
+        assert!(shape.eccentricity < 0.1);
+        assert!(shape.solidity > 0.9);
+        assert!((shape.extent - std::f64::consts::FRAC_PI_4).abs() < 0.05);
+        // Steps along the circle make it as long as its bounding square.
+        assert_eq!(shape.perimeter, 80);
+        // First Hu moment of a disc is 1 / 2π.
+        assert!((shape.hu_moments[0] - 1.0 / (2.0 * std::f64::consts::PI)).abs() < 0.01);
+    }
+
+    #[test]
+    fn hu_moments_are_invariant_to_translation() {
+        let cells = [(0, 0), (0, 1), (0, 2), (1, 2), (2, 2), (3, 1)];
+        let moved: Vec<(u32, u32)> = cells.iter().map(|(x, y)| (x + 7, y + 3)).collect();
+
+        let a = shape_descriptors(&mut object(Point::new(0, 0), &cells)).unwrap();
+        let b = shape_descriptors(&mut object(Point::new(20, 40), &moved)).unwrap();
+        for (a, b) in a.hu_moments.iter().zip(b.hu_moments.iter()) {
+            assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+        }
+        assert_eq!((a.area, a.perimeter), (b.area, b.perimeter));
+        assert!((a.eccentricity - b.eccentricity).abs() < 1e-12);
+    }
+
+    #[test]
+    fn perimeter_follows_outer_contour() {
+        // A ring, whose hole is not part of the contour.
+        let mut cells = rectangle(3, 3);
+        cells.retain(|cell| *cell != (1, 1));
+        let shape = shape_descriptors(&mut object(Point::new(0, 0), &cells)).unwrap();
+        assert_eq!(shape.perimeter, 12);
+
+        // Cells touching by their corners are one part.
+        let cells = [(0, 0), (1, 1)];
+        let shape = shape_descriptors(&mut object(Point::new(0, 0), &cells)).unwrap();
+        assert_eq!(shape.perimeter, 8);
+
+        let shape = shape_descriptors(&mut object(Point::new(0, 0), &rectangle(4, 4))).unwrap();
+        assert!((shape.circularity - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn perimeter_counts_every_part() {
+        // Two squares which do not touch, as merged by the post processing.
+        let mut cells = rectangle(2, 2);
+        cells.extend(rectangle(2, 2).into_iter().map(|(x, y)| (x + 4, y)));
+        let mut merged = object(Point::new(0, 0), &cells);
+        let shape = shape_descriptors(&mut merged).unwrap();
+
+        assert_eq!(shape.area, 8);
+        assert_eq!(shape.perimeter, 16);
+        // The contour only goes around the first one.
+        assert_eq!(
+            trace_contour(&mut merged).unwrap(),
+            points(&[(2, 0), (2, 2), (0, 2), (0, 0)])
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::{Archive, Builder, Header};
+
+/// Packs the highlights into tar shards in the WebDataset layout instead of
+/// persisting each of them as a separate file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sharding {
+    /// Maximum number of highlights in a shard.
+    pub shard_size: usize,
+}
+
+impl Default for Sharding {
+    fn default() -> Self {
+        Self { shard_size: 1000 }
+    }
+}
+
+/// Size of a block of a tar archive. The archive ends with two empty blocks.
+const BLOCK_SIZE: u64 = 512;
+
+/// Files of a single highlight. They are stored next to each other in a shard
+/// and their names start with the key followed by a dot. The metadata of the
+/// highlight, `{key}.json`, is the last of them.
+pub struct Sample {
+    pub key: String,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// Entry of the index file which lists the samples in each shard.
+#[derive(Deserialize, Serialize)]
+struct ShardIndex {
+    file: String,
+    samples: Vec<String>,
+}
+
+/// Appends samples to the shards of a single job one after another. Once a
+/// shard is full, it is closed and the next sample starts a new one.
+pub struct ShardWriter {
+    dir: PathBuf,
+    shard_size: usize,
+    current: Option<Builder<File>>,
+    index: Vec<ShardIndex>,
+
+    /// Shard of each sample written so far, by its key.
+    shards: HashMap<String, String>,
+}
+
+impl ShardWriter {
+    /// Creates a writer for shards in given directory. Shards which are already
+    /// there, e.g. from before a restart, are kept and new ones are numbered
+    /// after them.
+    pub fn new(dir: PathBuf, settings: &Sharding) -> Self {
+        let index = recover_index(&dir).unwrap_or_else(|error| {
+            println!("Cannot recover shards in {:?}: {}", dir, error);
+            Vec::new()
+        });
+        let shards = index
+            .iter()
+            .flat_map(|shard| {
+                let file = &shard.file;
+                shard
+                    .samples
+                    .iter()
+                    .map(move |key| (key.clone(), file.clone()))
+            })
+            .collect();
+
+        Self {
+            dir,
+            shard_size: settings.shard_size,
+            current: None,
+            index,
+            shards,
+        }
+    }
+
+    /// Appends the sample to the current shard. Returns the file name of the
+    /// shard the sample was written to. A sample with the same key as one which
+    /// is already in a shard, e.g. of a frame which was interrupted by a
+    /// restart after its samples were written, is not written again.
+    pub fn write(&mut self, sample: Sample) -> io::Result<String> {
+        if let Some(file) = self.shards.get(&sample.key) {
+            return Ok(file.clone());
+        }
+
+        if self.current.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let file = format!("shard_{:06}.tar", self.index.len());
+            self.current = Some(Builder::new(File::create(self.dir.join(&file))?));
+            self.index.push(ShardIndex {
+                file,
+                samples: Vec::new(),
+            });
+        }
+
+        let builder = self.current.as_mut().expect("Shard is open");
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        for (file, bytes) in sample.files.iter() {
+            let mut header = Header::new_ustar();
+            header.set_size(bytes.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            builder.append_data(&mut header, file, bytes.as_slice())?;
+        }
+
+        let shard = self.index.last_mut().expect("Shard is indexed");
+        let file = shard.file.clone();
+        self.shards.insert(sample.key.clone(), file.clone());
+        shard.samples.push(sample.key);
+
+        if shard.samples.len() >= self.shard_size {
+            self.close()?;
+        }
+
+        Ok(file)
+    }
+
+    /// Closes the current shard, if there is one, and rewrites the index so
+    /// that it lists all shards written so far.
+    pub fn close(&mut self) -> io::Result<()> {
+        if let Some(builder) = self.current.take() {
+            builder.into_inner()?;
+        }
+
+        if self.index.is_empty() {
+            return Ok(());
+        }
+
+        let file = File::create(self.dir.join("index.json"))?;
+        serde_json::to_writer_pretty(file, &self.index)?;
+        Ok(())
+    }
+}
+
+/// Reads the index of the shards in given directory. Shards which were not
+/// closed are missing from the index, therefore their samples are listed from
+/// the archive itself. Such a shard is cut after its last complete sample and
+/// ended the way a closed one is.
+fn recover_index(dir: &Path) -> io::Result<Vec<ShardIndex>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let index_path = dir.join("index.json");
+    let mut index: Vec<ShardIndex> = if index_path.is_file() {
+        serde_json::from_reader(File::open(index_path)?)?
+    } else {
+        Vec::new()
+    };
+
+    let mut unindexed: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|file| file.starts_with("shard_") && file.ends_with(".tar"))
+        .filter(|file| !index.iter().any(|shard| &shard.file == file))
+        .collect();
+    unindexed.sort();
+
+    for file in unindexed {
+        let samples = recover_shard(&dir.join(&file))?;
+        index.push(ShardIndex { file, samples });
+    }
+
+    Ok(index)
+}
+
+/// Lists the samples of a shard which was not closed, up to the last one whose
+/// metadata is complete. The rest of the archive is cut off and the two empty
+/// blocks which end an archive are appended.
+fn recover_shard(path: &Path) -> io::Result<Vec<String>> {
+    let length = fs::metadata(path)?.len();
+    let mut samples: Vec<String> = Vec::new();
+    let mut end: u64 = 0;
+
+    let mut archive = Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => break,
+        };
+        // Data of an entry is padded to whole blocks.
+        let size = match entry.header().entry_size() {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+        let entry_end =
+            entry.raw_file_position() + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+        if entry_end > length {
+            break;
+        }
+
+        let name = match entry.path() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => break,
+        };
+        // The metadata is the last file of a sample.
+        if let Some(key) = name.strip_suffix(".json") {
+            if !key.contains('.') {
+                samples.push(key.to_string());
+                end = entry_end;
+            }
+        }
+    }
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(end)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&[0; 2 * BLOCK_SIZE as usize])?;
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scratch::Scratch;
+    use super::*;
+
+    fn sample(key: &str) -> Sample {
+        Sample {
+            key: key.to_string(),
+            files: vec![
+                (format!("{}.png", key), vec![1, 2, 3]),
+                (format!("{}.json", key), b"{}".to_vec()),
+            ],
+        }
+    }
+
+    fn read_index(dir: &Path) -> Vec<ShardIndex> {
+        serde_json::from_reader(File::open(dir.join("index.json")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn full_shards_are_closed() {
+        let scratch = Scratch::new();
+        let mut writer = ShardWriter::new(scratch.to_path_buf(), &Sharding { shard_size: 2 });
+
+        let shards: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|key| writer.write(sample(key)).unwrap())
+            .collect();
+        assert_eq!(
+            shards,
+            vec!["shard_000000.tar", "shard_000000.tar", "shard_000001.tar"]
+        );
+
+        // The first shard is indexed once it is full, the second once closed.
+        assert_eq!(read_index(&scratch).len(), 1);
+        writer.close().unwrap();
+        let index = read_index(&scratch);
+        assert_eq!(index[0].samples, vec!["a", "b"]);
+        assert_eq!(index[1].samples, vec!["c"]);
+
+        let names = entries(&scratch.join("shard_000000.tar"));
+        assert_eq!(names, vec!["a.png", "a.json", "b.png", "b.json"]);
+    }
+
+    fn entries(path: &Path) -> Vec<String> {
+        let mut archive = Archive::new(File::open(path).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unclosed_shards_are_recovered() {
+        let scratch = Scratch::new();
+        let mut writer = ShardWriter::new(scratch.to_path_buf(), &Sharding { shard_size: 2 });
+        for key in ["a", "b", "c"].iter() {
+            writer.write(sample(key)).unwrap();
+        }
+        // The service stops before the last shard is closed, in the middle of
+        // the metadata of the next sample.
+        std::mem::forget(writer);
+        let mut torn = Builder::new(Vec::new());
+        for (file, bytes) in sample("d").files.iter() {
+            let mut header = Header::new_ustar();
+            header.set_size(bytes.len() as u64);
+            torn.append_data(&mut header, file, bytes.as_slice())
+                .unwrap();
+        }
+        let torn = torn.into_inner().unwrap();
+        let shard = scratch.join("shard_000001.tar");
+        let mut file = OpenOptions::new().append(true).open(&shard).unwrap();
+        file.write_all(&torn[..3 * BLOCK_SIZE as usize + 100])
+            .unwrap();
+
+        let index = recover_index(&scratch).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[1].file, "shard_000001.tar");
+        assert_eq!(index[1].samples, vec!["c"]);
+
+        // The shard is cut after the last complete sample and ended.
+        let length = fs::metadata(&shard).unwrap().len();
+        assert_eq!(length, 6 * BLOCK_SIZE);
+        assert_eq!(entries(&shard), vec!["c.png", "c.json"]);
+        recover_index(&scratch).unwrap();
+        assert_eq!(fs::metadata(&shard).unwrap().len(), length);
+
+        // Samples which are in a shard already are not written again, new ones
+        // go to a new shard after the recovered ones.
+        let mut writer = ShardWriter::new(scratch.to_path_buf(), &Sharding { shard_size: 2 });
+        assert_eq!(writer.write(sample("a")).unwrap(), "shard_000000.tar");
+        assert_eq!(writer.write(sample("c")).unwrap(), "shard_000001.tar");
+        assert_eq!(writer.write(sample("d")).unwrap(), "shard_000002.tar");
+        writer.close().unwrap();
+        let samples: Vec<Vec<String>> = read_index(&scratch)
+            .into_iter()
+            .map(|shard| shard.samples)
+            .collect();
+        assert_eq!(samples, vec![vec!["a", "b"], vec!["c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn nothing_to_recover() {
+        let scratch = Scratch::new();
+        assert!(recover_index(&scratch.join("missing")).unwrap().is_empty());
+        assert!(recover_index(&scratch).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,624 @@
+use super::detect::{detect, Detection};
+use super::extract_highlights::MAX_CELLS;
+use super::grid::Rect;
+use super::heat_map::CELL_SIZE;
+use super::helpers::is_image;
+use super::image::{self, DynamicImage};
+use super::labels::{read_labels, Agreement, Labels, Scores, IOU_THRESHOLD};
+use super::settings::Settings;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+/// Name of the file with the default settings of a directory in its output
+/// directory.
+pub const DEFAULTS: &str = "defaults.json";
+
+/// Name of the file with the report of the last sweep of a directory in its
+/// output directory.
+pub const SWEEP_REPORT: &str = "sweep.json";
+
+/// Largest number of combinations of parameters a sweep can try.
+pub const MAX_COMBINATIONS: usize = 64;
+
+/// Largest number of frames a sweep can sample. All of them are decoded and
+/// kept in memory while the sweep runs.
+pub const MAX_SAMPLE: usize = 100;
+
+/// Grid of parameters to run the pipeline with on a sample of frames.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sweep {
+    pub cell_sizes: Vec<u32>,
+    pub max_cells: Vec<u32>,
+
+    /// Number of frames, evenly spread over the directory, the pipeline runs
+    /// on.
+    pub sample: usize,
+
+    /// Parameters which are not swept. The defaults of the directory are used
+    /// if not given.
+    pub settings: Option<Settings>,
+
+    /// What the best parameters are. If given, they become the defaults of the
+    /// directory.
+    pub target: Option<Target>,
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self {
+            cell_sizes: vec![CELL_SIZE],
+            max_cells: vec![MAX_CELLS],
+            sample: 10,
+            settings: None,
+            target: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Target {
+    /// Parameters which find about this many highlights in each frame.
+    HighlightsPerFrame(f64),
+    /// Parameters whose highlights agree the most with the boxes in given COCO
+    /// file or directory of VOC files in the output directory. They are not
+    /// with the images, so that they are not processed as one. Only labelled
+    /// frames are sampled.
+    Labels(String),
+}
+
+/// Results of the pipeline on the sampled frames with each combination of
+/// parameters.
+#[derive(Debug, Serialize)]
+pub struct SweepReport {
+    pub frames: Vec<String>,
+    pub results: Vec<SweepResult>,
+
+    /// Settings with the best parameters which were stored as the defaults of
+    /// the directory, if there is a target.
+    pub defaults: Option<Settings>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SweepResult {
+    pub cell_size: u32,
+    pub max_cells: u32,
+
+    /// Number of highlights per frame.
+    pub highlights: Distribution,
+
+    /// Area of the rectangles of the highlights in pixels.
+    pub areas: Distribution,
+
+    /// Mean time the pipeline took per frame in milliseconds.
+    pub runtime_ms: f64,
+
+    /// Agreement with the labelled boxes if the target is labels.
+    pub agreement: Option<Scores>,
+
+    /// How close the parameters are to the target, the higher the better.
+    pub score: Option<f64>,
+}
+
+/// Summary of a set of values.
+#[derive(Debug, Default, Serialize)]
+pub struct Distribution {
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Sweep {
+    /// Checks that the sweep is not empty or too large and that the pipeline can
+    /// run with each combination.
+    pub fn validate(&self) -> Result<(), String> {
+        let combinations = self.cell_sizes.len() * self.max_cells.len();
+        if combinations == 0 || combinations > MAX_COMBINATIONS {
+            return Err(format!(
+                "Sweep has to try between 1 and {} combinations.",
+                MAX_COMBINATIONS
+            ));
+        }
+
+        if self.sample == 0 || self.sample > MAX_SAMPLE {
+            return Err(format!(
+                "Sample has to have between 1 and {} frames.",
+                MAX_SAMPLE
+            ));
+        }
+
+        match &self.target {
+            Some(Target::HighlightsPerFrame(target)) if !(*target >= 0.0 && target.is_finite()) => {
+                return Err(format!("Target of {} highlights is not a count.", target));
+            }
+            Some(Target::Labels(file))
+                if file.is_empty() || file.contains('/') || file.starts_with('.') =>
+            {
+                return Err("Labels have to be a file in the output directory.".to_string());
+            }
+            _ => {}
+        }
+
+        let base = self.settings.clone().unwrap_or_default();
+        for (cell_size, max_cells) in self.combinations() {
+            Settings {
+                cell_size,
+                max_cells,
+                adaptive: None,
+                ..base.clone()
+            }
+            .validate()?;
+        }
+
+        Ok(())
+    }
+
+    fn combinations(&self) -> Vec<(u32, u32)> {
+        self.cell_sizes
+            .iter()
+            .flat_map(|cell_size| {
+                self.max_cells
+                    .iter()
+                    .map(move |max_cells| (*cell_size, *max_cells))
+            })
+            .collect()
+    }
+}
+
+/// Runs sweeps on the pool of the worker, so that they share the threads with
+/// the frames of the queue, and persists their reports. A directory is swept
+/// once at a time.
+pub struct Sweeper {
+    pool: Arc<ThreadPool>,
+
+    /// Names of the directories which are being swept.
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Keeps the directory among the running sweeps until the sweep ends, even if
+/// it panics.
+struct Running {
+    running: Arc<Mutex<HashSet<String>>>,
+    name: String,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.name);
+    }
+}
+
+impl Sweeper {
+    pub fn new(pool: Arc<ThreadPool>) -> Self {
+        Self {
+            pool,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Starts the sweep of the directory with given name on the pool and
+    /// removes the report of the previous one. Once the sweep ends, its report
+    /// or the reason it failed is persisted. Returns false if the directory is
+    /// being swept already. The sweep has to be valid.
+    pub fn start(
+        &self,
+        name: &str,
+        input_dir: PathBuf,
+        output_dir: PathBuf,
+        request: Sweep,
+    ) -> io::Result<bool> {
+        let running = {
+            let mut running = self.running.lock().expect("Running sweeps are poisoned");
+            if !running.insert(name.to_string()) {
+                return Ok(false);
+            }
+            Running {
+                running: Arc::clone(&self.running),
+                name: name.to_string(),
+            }
+        };
+
+        let report_path = output_dir.join(SWEEP_REPORT);
+        if report_path.is_file() {
+            fs::remove_file(&report_path)?;
+        }
+
+        self.pool.spawn(move || {
+            let name = &running.name;
+            let result = sweep(&input_dir, &output_dir, request);
+            match &result {
+                Ok(SweepReport {
+                    defaults: Some(defaults),
+                    ..
+                }) => println!(
+                    "Cell size {} and max cells {} are the defaults of {}.",
+                    defaults.cell_size, defaults.max_cells, name
+                ),
+                Ok(_) => println!("Swept {}.", name),
+                Err(error) => println!("Cannot sweep {:?}: {}.", input_dir, error),
+            }
+
+            if let Err(error) = store_report(&output_dir, &result) {
+                println!("Cannot store the sweep report of {}: {}.", name, error);
+            }
+        });
+
+        Ok(true)
+    }
+
+    /// Whether the directory with given name is being swept.
+    pub fn is_running(&self, name: &str) -> bool {
+        self.running
+            .lock()
+            .expect("Running sweeps are poisoned")
+            .contains(name)
+    }
+}
+
+/// Runs the pipeline with each combination of the sweep on a sample of the
+/// images in the input directory. If the sweep has a target, the best
+/// parameters are stored as the defaults in the output directory. The sweep
+/// has to be valid.
+pub fn sweep(input_dir: &Path, output_dir: &Path, request: Sweep) -> io::Result<SweepReport> {
+    let labels: Option<Labels> = match &request.target {
+        Some(Target::Labels(file)) => Some(read_labels(&output_dir.join(file))?),
+        _ => None,
+    };
+
+    let mut frames: Vec<String> = Vec::new();
+    for entry in fs::read_dir(input_dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_file() && is_image(&path) => name.to_string(),
+            _ => continue,
+        };
+
+        if labels
+            .as_ref()
+            .map_or(true, |labels| labels.contains_key(&name))
+        {
+            frames.push(name);
+        }
+    }
+    frames.sort();
+    let frames = sample(frames, request.sample);
+    if frames.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "There are no frames to sample",
+        ));
+    }
+
+    let images: Vec<DynamicImage> = frames
+        .iter()
+        .map(|frame| image::open(input_dir.join(frame)))
+        .collect::<Result<_, _>>()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let base = match &request.settings {
+        Some(settings) => settings.clone(),
+        None => read_defaults(output_dir)?.unwrap_or_default(),
+    };
+
+    let mut results: Vec<SweepResult> = Vec::new();
+    for (cell_size, max_cells) in request.combinations() {
+        // The swept cell size is used as it is, the adaptive search would
+        // choose its own. The pyramid does not change the highlights and is
+        // left out of the runtime.
+        let settings = Settings {
+            cell_size,
+            max_cells,
+            adaptive: None,
+            pyramid: None,
+            ..base.clone()
+        };
+
+        // Each frame is timed on its own, so that the runtime does not depend
+        // on how many frames run in parallel.
+        let detections: Vec<(f64, Vec<Rect>)> = images
+            .par_iter()
+            .map(|image| {
+                let start = Instant::now();
+                let Detection {
+                    grid,
+                    mut highlights,
+                    ..
+                } = detect(image.clone(), &settings);
+                let elapsed = start.elapsed();
+
+                let rects = highlights
+                    .iter_mut()
+                    .filter_map(|highlight| highlight.bounds())
+                    .map(|bounds| grid.bounds_to_pixels(bounds, 0))
+                    .collect();
+                (elapsed.as_secs_f64() * 1000.0, rects)
+            })
+            .collect();
+
+        let counts: Vec<f64> = detections
+            .iter()
+            .map(|(_, rects)| rects.len() as f64)
+            .collect();
+        let areas: Vec<f64> = detections
+            .iter()
+            .flat_map(|(_, rects)| rects.iter().map(|rect| rect.area() as f64))
+            .collect();
+        let runtime_ms = detections.iter().map(|(ms, _)| ms).sum::<f64>() / images.len() as f64;
+
+        let agreement = labels.as_ref().map(|labels| {
+            let mut agreement = Agreement::default();
+            for (frame, (_, rects)) in frames.iter().zip(detections.iter()) {
+                agreement.add(&Agreement::new(rects, &labels[frame], IOU_THRESHOLD));
+            }
+            agreement
+        });
+
+        let score = match &request.target {
+            None => None,
+            // Frames far from the target count more than a mean close to it.
+            Some(Target::HighlightsPerFrame(target)) => Some(
+                -counts
+                    .iter()
+                    .map(|count| (count - target).abs())
+                    .sum::<f64>()
+                    / counts.len() as f64,
+            ),
+            Some(Target::Labels(_)) => agreement.as_ref().map(Agreement::f1),
+        };
+
+        results.push(SweepResult {
+            cell_size,
+            max_cells,
+            highlights: Distribution::new(counts),
+            areas: Distribution::new(areas),
+            runtime_ms,
+            agreement: agreement.as_ref().map(Scores::from),
+            score,
+        });
+    }
+
+    // The first of the combinations with the same score wins.
+    let mut best: Option<&SweepResult> = None;
+    for result in results.iter() {
+        if let Some(score) = result.score {
+            if best
+                .and_then(|best| best.score)
+                .map_or(true, |best| score > best)
+            {
+                best = Some(result);
+            }
+        }
+    }
+
+    let defaults = match best {
+        None => None,
+        Some(best) => {
+            let settings = Settings {
+                cell_size: best.cell_size,
+                max_cells: best.max_cells,
+                adaptive: None,
+                ..base
+            };
+            store_defaults(output_dir, &settings)?;
+            Some(settings)
+        }
+    };
+
+    Ok(SweepReport {
+        frames,
+        results,
+        defaults,
+    })
+}
+
+/// Reads the settings a request for the directory uses if it gives none.
+pub fn read_defaults(output_dir: &Path) -> io::Result<Option<Settings>> {
+    let path = output_dir.join(DEFAULTS);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_reader(File::open(path)?)?))
+}
+
+/// Persists the report of a sweep, or the reason why it failed.
+fn store_report(output_dir: &Path, result: &io::Result<SweepReport>) -> io::Result<()> {
+    fs::create_dir_all(output_dir)?;
+    let path = output_dir.join(SWEEP_REPORT);
+    let temporary = output_dir.join(format!("{}.tmp", SWEEP_REPORT));
+
+    let file = File::create(&temporary)?;
+    match result {
+        Ok(report) => serde_json::to_writer_pretty(file, report)?,
+        Err(error) => {
+            serde_json::to_writer_pretty(file, &serde_json::json!({ "error": error.to_string() }))?
+        }
+    }
+    fs::rename(temporary, path)
+}
+
+fn store_defaults(output_dir: &Path, settings: &Settings) -> io::Result<()> {
+    fs::create_dir_all(output_dir)?;
+    let path = output_dir.join(DEFAULTS);
+    let temporary = output_dir.join(format!("{}.tmp", DEFAULTS));
+
+    serde_json::to_writer_pretty(File::create(&temporary)?, settings)?;
+    fs::rename(temporary, path)
+}
+
+/// Picks given number of frames evenly spread over all of them.
+fn sample(frames: Vec<String>, size: usize) -> Vec<String> {
+    if frames.len() <= size {
+        return frames;
+    }
+
+    let step = frames.len() as f64 / size as f64;
+    (0..size)
+        .map(|i| frames[(i as f64 * step) as usize].clone())
+        .collect()
+}
+
+impl Distribution {
+    fn new(mut values: Vec<f64>) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        values.sort_by(|a, b| a.total_cmp(b));
+        let quantile = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+
+        Self {
+            min: values[0],
+            p25: quantile(0.25),
+            median: quantile(0.5),
+            p75: quantile(0.75),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scratch::Scratch;
+    use super::super::synthetic::{generate, SceneSpec};
+    use super::*;
+    use rayon::ThreadPoolBuilder;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Report of the sweep once the directory is not being swept anymore.
+    fn wait_for_report(sweeper: &Sweeper, name: &str, output_dir: &Path) -> serde_json::Value {
+        for _ in 0..1000 {
+            if !sweeper.is_running(name) {
+                let file = File::open(output_dir.join(SWEEP_REPORT)).unwrap();
+                return serde_json::from_reader(file).unwrap();
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Sweep of {} did not end", name);
+    }
+
+    #[test]
+    fn sample_is_bounded() {
+        let sweep = |sample| Sweep {
+            sample,
+            ..Sweep::default()
+        };
+
+        assert!(sweep(0).validate().is_err());
+        assert!(sweep(MAX_SAMPLE).validate().is_ok());
+        assert!(sweep(MAX_SAMPLE + 1).validate().is_err());
+    }
+
+    #[test]
+    fn target_is_a_count() {
+        let sweep = |target| Sweep {
+            target: Some(Target::HighlightsPerFrame(target)),
+            ..Sweep::default()
+        };
+
+        assert!(sweep(0.0).validate().is_ok());
+        assert!(sweep(4.5).validate().is_ok());
+        for target in [-1.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(sweep(*target).validate().is_err(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn sample_spreads_over_frames() {
+        let frames: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+
+        assert_eq!(sample(frames.clone(), 3), vec!["0", "3", "6"]);
+        assert_eq!(sample(frames.clone(), 20), frames);
+    }
+
+    #[test]
+    fn sweeps_run_on_the_pool_and_persist_reports() {
+        let scratch = Scratch::new();
+        let (input_dir, output_dir) = (scratch.join("in"), scratch.join("out"));
+        fs::create_dir_all(&input_dir).unwrap();
+        for seed in 0..2 {
+            generate(&SceneSpec {
+                width: 160,
+                height: 90,
+                objects: 2,
+                seed,
+            })
+            .image
+            .save(input_dir.join(format!("{}.png", seed)))
+            .unwrap();
+        }
+
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let sweeper = Sweeper::new(Arc::new(pool));
+        let request = Sweep {
+            cell_sizes: vec![8, 10],
+            target: Some(Target::HighlightsPerFrame(2.0)),
+            ..Sweep::default()
+        };
+        assert!(sweeper
+            .start(
+                "dir",
+                input_dir.clone(),
+                output_dir.clone(),
+                request.clone()
+            )
+            .unwrap());
+
+        let report = wait_for_report(&sweeper, "dir", &output_dir);
+        assert_eq!(report["frames"], serde_json::json!(["0.png", "1.png"]));
+        assert_eq!(report["results"].as_array().unwrap().len(), 2);
+        assert!(read_defaults(&output_dir).unwrap().is_some());
+
+        // A sweep which fails persists the reason.
+        let empty_dir = scratch.join("empty");
+        fs::create_dir_all(&empty_dir).unwrap();
+        assert!(sweeper
+            .start("dir", empty_dir, output_dir.clone(), request)
+            .unwrap());
+        let report = wait_for_report(&sweeper, "dir", &output_dir);
+        assert!(report["error"].is_string());
+    }
+
+    #[test]
+    fn directory_is_swept_once_at_a_time() {
+        let scratch = Scratch::new();
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let sweeper = Sweeper::new(Arc::new(pool));
+
+        // The directory is taken until the sweep ends.
+        let running = Running {
+            running: Arc::clone(&sweeper.running),
+            name: "dir".to_string(),
+        };
+        sweeper.running.lock().unwrap().insert("dir".to_string());
+        let start = || {
+            sweeper.start(
+                "dir",
+                scratch.join("in"),
+                scratch.join("out"),
+                Sweep::default(),
+            )
+        };
+        assert!(!start().unwrap());
+        assert!(sweeper.is_running("dir"));
+
+        drop(running);
+        assert!(!sweeper.is_running("dir"));
+    }
+}
@@ -0,0 +1,387 @@
+use super::grid::Rect;
+use super::image::{DynamicImage, GrayImage, ImageRgb8, Luma, Rgb, RgbImage};
+
+/// Generates cartoon-like scenes with exactly known objects, so that the
+/// pipeline can be tested without copyrighted frames. The same spec always
+/// renders the same scene.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneSpec {
+    pub width: u32,
+    pub height: u32,
+
+    /// Number of objects to place. Fewer are placed if they do not fit.
+    pub objects: usize,
+
+    pub seed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Rectangle,
+    Ellipse,
+    Triangle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Texture {
+    Flat,
+    /// Diagonal stripes of a darker shade with given period in pixels.
+    Stripes(u32),
+    /// Grid of darker dots with given period in pixels.
+    Dots(u32),
+}
+
+/// Object drawn into the scene.
+pub struct SceneObject {
+    pub shape: Shape,
+    pub texture: Texture,
+
+    /// Smallest rectangle in pixels with all pixels of the object, including
+    /// its outline.
+    pub rect: Rect,
+
+    /// Pixels of the object within its rectangle, 255 for the object and 0 for
+    /// the background.
+    pub mask: GrayImage,
+}
+
+pub struct Scene {
+    pub image: DynamicImage,
+    pub objects: Vec<SceneObject>,
+}
+
+impl Scene {
+    /// Rectangles of all objects, which is what the highlights should find.
+    pub fn labels(&self) -> Vec<Rect> {
+        self.objects.iter().map(|object| object.rect).collect()
+    }
+}
+
+/// Flat-colour shapes with dark outlines and textures on a background with a
+/// gentle brightness gradient. Objects do not overlap and keep a gap between
+/// each other and from the edges of the image, so that each of them is a
+/// separate highlight.
+pub fn generate(spec: &SceneSpec) -> Scene {
+    let mut rng = Rng::new(spec.seed);
+    let (width, height) = (spec.width, spec.height);
+
+    // The gradient is shallow enough not to be mistaken for edges.
+    let base = rng.range(150, 220) as f64;
+    let gradient_x = rng.signed(20.0) / width as f64;
+    let gradient_y = rng.signed(20.0) / height as f64;
+    let tint = [rng.signed(8.0), rng.signed(8.0), rng.signed(8.0)];
+    let mut image = RgbImage::from_fn(width, height, |x, y| {
+        let brightness = base + gradient_x * x as f64 + gradient_y * y as f64;
+        Rgb([
+            channel(brightness + tint[0]),
+            channel(brightness + tint[1]),
+            channel(brightness + tint[2]),
+        ])
+    });
+
+    let shortest = width.min(height);
+    let (smallest, largest) = ((shortest / 8).max(8), (shortest / 4).max(12));
+    let margin = (shortest / 20).max(4);
+
+    let mut objects: Vec<SceneObject> = Vec::new();
+    for _ in 0..spec.objects {
+        // Places the object where there is room for it, giving up after a
+        // while if the scene is full.
+        let placed = (0..100).find_map(|_| {
+            let w = rng.range(smallest, largest + 1);
+            let h = rng.range(smallest, largest + 1);
+            if w + 2 * margin >= width || h + 2 * margin >= height {
+                return None;
+            }
+
+            let rect = Rect {
+                x: rng.range(margin, width - w - margin),
+                y: rng.range(margin, height - h - margin),
+                width: w,
+                height: h,
+            };
+            let free = objects
+                .iter()
+                .all(|object| !overlaps(expand(object.rect, margin), rect));
+            Some(rect).filter(|_| free)
+        });
+        let area = match placed {
+            None => break,
+            Some(area) => area,
+        };
+
+        let shape = match rng.range(0, 3) {
+            0 => Shape::Rectangle,
+            1 => Shape::Ellipse,
+            _ => Shape::Triangle,
+        };
+        let texture = match rng.range(0, 3) {
+            0 => Texture::Flat,
+            1 => Texture::Stripes(rng.range(4, 10)),
+            _ => Texture::Dots(rng.range(5, 10)),
+        };
+        let fill = [
+            rng.range(40, 220) as f64,
+            rng.range(40, 220) as f64,
+            rng.range(40, 220) as f64,
+        ];
+        let outline = rng.range(10, 40) as f64;
+        let thickness = (area.width.min(area.height) as f64 / 25.0).max(2.0);
+        let apex = rng.unit();
+
+        let mut mask = GrayImage::new(area.width, area.height);
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let (inside, border) = shape_at(shape, area, x, y, thickness, apex);
+                if !inside {
+                    continue;
+                }
+
+                let shade = if border {
+                    [outline; 3]
+                } else if is_textured(texture, x, y) {
+                    [fill[0] * 0.7, fill[1] * 0.7, fill[2] * 0.7]
+                } else {
+                    fill
+                };
+                image.put_pixel(
+                    area.x + x,
+                    area.y + y,
+                    Rgb([channel(shade[0]), channel(shade[1]), channel(shade[2])]),
+                );
+                mask.put_pixel(x, y, Luma([255]));
+            }
+        }
+
+        let (rect, mask) = trim(area, mask);
+        objects.push(SceneObject {
+            shape,
+            texture,
+            rect,
+            mask,
+        });
+    }
+
+    Scene {
+        image: ImageRgb8(image),
+        objects,
+    }
+}
+
+/// Whether the pixel at given position within the area belongs to the shape
+/// and whether it is a part of its outline.
+fn shape_at(shape: Shape, area: Rect, x: u32, y: u32, thickness: f64, apex: f64) -> (bool, bool) {
+    let (w, h) = (area.width as f64, area.height as f64);
+    let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+
+    match shape {
+        Shape::Rectangle => {
+            let distance = px.min(py).min(w - px).min(h - py);
+            (true, distance < thickness)
+        }
+        Shape::Ellipse => {
+            let (a, b) = (w / 2.0, h / 2.0);
+            let (dx, dy) = (px - a, py - b);
+            let inside = |a: f64, b: f64| (dx / a).powi(2) + (dy / b).powi(2) <= 1.0;
+            let inner = a > thickness && b > thickness && inside(a - thickness, b - thickness);
+            (inside(a, b), !inner)
+        }
+        Shape::Triangle => {
+            // The apex is on the top edge, the base is the bottom edge.
+            let vertices = [(apex * w, 0.0), (w, h), (0.0, h)];
+            let mut distance = f64::MAX;
+            for i in 0..3 {
+                let (ax, ay) = vertices[i];
+                let (bx, by) = vertices[(i + 1) % 3];
+                let length = ((bx - ax).powi(2) + (by - ay).powi(2)).sqrt();
+                // Positive inside of the clockwise triangle.
+                let signed = ((bx - ax) * (py - ay) - (by - ay) * (px - ax)) / length;
+                distance = distance.min(signed);
+            }
+            (distance >= 0.0, distance < thickness)
+        }
+    }
+}
+
+fn is_textured(texture: Texture, x: u32, y: u32) -> bool {
+    match texture {
+        Texture::Flat => false,
+        Texture::Stripes(period) => (x + y) % period < period / 2,
+        Texture::Dots(period) => {
+            let (dx, dy) = (x % period, y % period);
+            let centre = period / 2;
+            (dx as i64 - centre as i64).pow(2) + (dy as i64 - centre as i64).pow(2) <= 2
+        }
+    }
+}
+
+/// Shrinks the area to the pixels of the mask.
+fn trim(area: Rect, mask: GrayImage) -> (Rect, GrayImage) {
+    let pixels: Vec<(u32, u32)> = mask
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.data[0] > 0)
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    let left = pixels.iter().map(|(x, _)| *x).min().unwrap_or(0);
+    let top = pixels.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    let right = pixels.iter().map(|(x, _)| *x + 1).max().unwrap_or(0);
+    let bottom = pixels.iter().map(|(_, y)| *y + 1).max().unwrap_or(0);
+
+    let trimmed = GrayImage::from_fn(right - left, bottom - top, |x, y| {
+        *mask.get_pixel(left + x, top + y)
+    });
+    let rect = Rect {
+        x: area.x + left,
+        y: area.y + top,
+        width: right - left,
+        height: bottom - top,
+    };
+    (rect, trimmed)
+}
+
+fn expand(rect: Rect, by: u32) -> Rect {
+    Rect {
+        x: rect.x.saturating_sub(by),
+        y: rect.y.saturating_sub(by),
+        width: rect.width + 2 * by,
+        height: rect.height + 2 * by,
+    }
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn channel(value: f64) -> u8 {
+    value.clamp(0.0, 255.0).round() as u8
+}
+
+/// Small deterministic generator of pseudo random numbers (xorshift64*), so
+/// that the scenes do not depend on a crate or the platform.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Spreads similar seeds apart, the state cannot be zero.
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng((state ^ (state >> 31)) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Number from the lower bound up to, but not including, the upper one.
+    pub fn range(&mut self, low: u32, high: u32) -> u32 {
+        low + (self.next_u64() % u64::from(high - low)) as u32
+    }
+
+    /// Number between 0 and 1.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Number between `-magnitude` and `magnitude`.
+    pub fn signed(&mut self, magnitude: f64) -> f64 {
+        (self.unit() * 2.0 - 1.0) * magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(seed: u64) -> SceneSpec {
+        SceneSpec {
+            width: 320,
+            height: 180,
+            objects: 4,
+            seed,
+        }
+    }
+
+    #[test]
+    fn scenes_are_deterministic() {
+        let a = generate(&spec(7));
+        let b = generate(&spec(7));
+        let c = generate(&spec(8));
+
+        assert_eq!(a.image.raw_pixels(), b.image.raw_pixels());
+        assert_eq!(a.labels(), b.labels());
+        assert_ne!(a.image.raw_pixels(), c.image.raw_pixels());
+    }
+
+    #[test]
+    fn masks_fill_their_rects() {
+        for seed in 0..8 {
+            let scene = generate(&spec(seed));
+            assert!(!scene.objects.is_empty());
+
+            for object in scene.objects.iter() {
+                let (width, height) = object.mask.dimensions();
+                assert_eq!((width, height), (object.rect.width, object.rect.height));
+
+                // The rectangle is tight, therefore each of its edges touches
+                // the object.
+                let on = |x: u32, y: u32| object.mask.get_pixel(x, y).data[0] > 0;
+                assert!((0..height).any(|y| on(0, y)));
+                assert!((0..height).any(|y| on(width - 1, y)));
+                assert!((0..width).any(|x| on(x, 0)));
+                assert!((0..width).any(|x| on(x, height - 1)));
+            }
+        }
+    }
+
+    #[test]
+    fn objects_do_not_overlap() {
+        for seed in 0..8 {
+            let labels = generate(&spec(seed)).labels();
+
+            for (i, a) in labels.iter().enumerate() {
+                for b in labels.iter().skip(i + 1) {
+                    assert!(!overlaps(*a, *b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scenes_vary_shapes_and_textures() {
+        let objects: Vec<SceneObject> = (0..8)
+            .flat_map(|seed| generate(&spec(seed)).objects)
+            .collect();
+
+        for shape in [Shape::Rectangle, Shape::Ellipse, Shape::Triangle].iter() {
+            assert!(objects.iter().any(|object| object.shape == *shape));
+        }
+        assert!(objects.iter().any(|object| object.texture == Texture::Flat));
+        assert!(objects
+            .iter()
+            .any(|object| matches!(object.texture, Texture::Stripes(_))));
+        assert!(objects
+            .iter()
+            .any(|object| matches!(object.texture, Texture::Dots(_))));
+    }
+
+    #[test]
+    fn scenes_have_arbitrary_resolution() {
+        for (width, height) in [(64, 48), (640, 360), (1000, 1000)].iter() {
+            let scene = generate(&SceneSpec {
+                width: *width,
+                height: *height,
+                objects: 3,
+                seed: 1,
+            });
+
+            assert_eq!(scene.image.to_rgb().dimensions(), (*width, *height));
+            for rect in scene.labels() {
+                assert!(rect.x + rect.width <= *width && rect.y + rect.height <= *height);
+            }
+        }
+    }
+}
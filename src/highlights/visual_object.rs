@@ -1,8 +1,10 @@
 use super::helpers::pixel_value;
 use super::point::Point;
+use std::collections::HashSet;
 use std::fmt;
 
 type PointMap = Vec<Vec<bool>>;
+type GrayImageRaw = Vec<Vec<u32>>;
 
 pub struct VisualObject {
     /// Maps the point to the original picture.
@@ -11,6 +13,10 @@ pub struct VisualObject {
     /// Vector off all points the object contains.
     pub points: Vec<Point>,
 
+    /// How much the object stands out of the image, between 0 and 1. It is the
+    /// mean heat of its points relative to the maximum heat in the heat map.
+    pub saliency: f32,
+
     /// First point is the left most point with the lowest y value.
     /// Second point the right most point with the highest y value.
     size: Option<(Point, Point)>,
@@ -21,6 +27,7 @@ impl VisualObject {
     pub fn new(reference: Point) -> VisualObject {
         VisualObject {
             points: Vec::new(),
+            saliency: 0_f32,
             size: None,
             reference,
         }
@@ -35,7 +42,7 @@ impl VisualObject {
     /// Returns a tuple with points defining the smallest encapsulating rectangle,
     /// meaning all points are within this rectangle.
     pub fn size(&mut self) -> Option<(Point, Point)> {
-        if self.points.len() == 0 {
+        if self.points.is_empty() {
             return None;
         }
 
@@ -67,14 +74,69 @@ impl VisualObject {
         self.size
     }
 
-    pub fn point_map(&mut self) -> Option<PointMap> {
-        let size = self.size();
+    /// Returns the smallest encapsulating rectangle in the coordinates of the
+    /// whole heat map rather than relative to the reference point.
+    pub fn bounds(&mut self) -> Option<(Point, Point)> {
+        let (lower, higher) = self.size()?;
 
-        if size.is_none() {
-            return None;
+        Some((lower + self.reference, higher + self.reference))
+    }
+
+    /// Calculates the saliency of the object from the heat map its points were
+    /// extracted from.
+    pub fn measure_saliency(&mut self, heat_map: &GrayImageRaw, heat_max: u32) {
+        if self.points.is_empty() {
+            self.saliency = 0_f32;
+            return;
+        }
+
+        let heat_total: u32 = self
+            .points
+            .iter()
+            .map(|point| *point + self.reference)
+            .map(|point| pixel_value(heat_map, point.x as isize, point.y as isize, 0))
+            .sum();
+
+        self.saliency = heat_total as f32 / (self.points.len() as u32 * heat_max.max(1)) as f32;
+    }
+
+    /// Joins two objects into one. The saliency of the result is the mean of
+    /// both saliencies weighted by the number of points.
+    pub fn merge(self, other: VisualObject) -> VisualObject {
+        let reference = Point::new(
+            self.reference.x.min(other.reference.x),
+            self.reference.y.min(other.reference.y),
+        );
+        let points_n = (self.points.len() + other.points.len()).max(1) as f32;
+
+        let mut merged = VisualObject::new(reference);
+        merged.saliency = (self.saliency * self.points.len() as f32
+            + other.saliency * other.points.len() as f32)
+            / points_n;
+
+        // Points both objects have are kept once.
+        let mut seen: HashSet<(u32, u32)> = HashSet::new();
+        for object in [self, other].iter() {
+            // Both references are at least as large as the new one, therefore
+            // the subtraction cannot underflow.
+            let offset = Point::new(
+                object.reference.x - reference.x,
+                object.reference.y - reference.y,
+            );
+
+            for point in object.points.iter() {
+                let point = *point + offset;
+                if seen.insert((point.x, point.y)) {
+                    merged.push(point);
+                }
+            }
         }
 
-        let (lower, higher) = size?;
+        merged
+    }
+
+    pub fn point_map(&mut self) -> Option<PointMap> {
+        let (lower, higher) = self.size()?;
 
         let mut map: PointMap = Vec::new();
 
@@ -96,11 +158,13 @@ impl VisualObject {
         let map = self.point_map()?;
         let (lower, higher) = self.size()?;
 
+        // The ranges are inclusive so that the peeled map has the same dimensions
+        // as the original one and is aligned with the same lower point.
         let mut peeled_map: PointMap = Vec::new();
-        for y in 0..(higher.y - lower.y) {
+        for y in 0..=(higher.y - lower.y) {
             let mut row: Vec<bool> = Vec::new();
 
-            for x in 0..(higher.x - lower.x) {
+            for x in 0..=(higher.x - lower.x) {
                 row.push(is_neighbourhood_highlighted(&map, x, y));
             }
 
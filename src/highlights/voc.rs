@@ -0,0 +1,210 @@
+use super::grid::Rect;
+use super::labels::Labels;
+use super::metadata::FrameMetadata;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Writes an annotation file in the Pascal VOC format for each frame into given
+/// directory. The files are named after the frames.
+pub fn write(frames: &[FrameMetadata], folder: &str, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    for frame in frames.iter() {
+        let stem = Path::new(&frame.frame)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(&frame.frame);
+
+        fs::write(dir.join(format!("{}.xml", stem)), annotation(frame, folder))?;
+    }
+
+    Ok(())
+}
+
+/// Renders the XML document of a single frame. The format uses pixel
+/// coordinates starting at 1 with both ends of the box included.
+fn annotation(frame: &FrameMetadata, folder: &str) -> String {
+    let mut xml = String::new();
+
+    // Writing into a string cannot fail.
+    let _ = write!(
+        xml,
+        concat!(
+            "<annotation>\n",
+            "  <folder>{}</folder>\n",
+            "  <filename>{}</filename>\n",
+            "  <size>\n",
+            "    <width>{}</width>\n",
+            "    <height>{}</height>\n",
+            "    <depth>3</depth>\n",
+            "  </size>\n",
+            "  <segmented>0</segmented>\n",
+        ),
+        escape(folder),
+        escape(&frame.frame),
+        frame.width,
+        frame.height
+    );
+
+    for highlight in frame.highlights.iter() {
+        let rect = highlight.rect;
+
+        // Objects cut off by the edge of the image are marked as truncated.
+        let truncated = rect.x == 0
+            || rect.y == 0
+            || rect.x + rect.width >= frame.width
+            || rect.y + rect.height >= frame.height;
+
+        let _ = write!(
+            xml,
+            concat!(
+                "  <object>\n",
+                "    <name>highlight</name>\n",
+                "    <pose>Unspecified</pose>\n",
+                "    <truncated>{}</truncated>\n",
+                "    <difficult>0</difficult>\n",
+                "    <bndbox>\n",
+                "      <xmin>{}</xmin>\n",
+                "      <ymin>{}</ymin>\n",
+                "      <xmax>{}</xmax>\n",
+                "      <ymax>{}</ymax>\n",
+                "    </bndbox>\n",
+                "  </object>\n",
+            ),
+            truncated as u8,
+            rect.x + 1,
+            rect.y + 1,
+            rect.x + rect.width,
+            rect.y + rect.height
+        );
+    }
+
+    xml.push_str("</annotation>\n");
+    xml
+}
+
+/// Escapes the characters which have a special meaning in XML.
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Reads the boxes from all annotation files in given directory, by the file
+/// name of their image. Only the elements the boxes need are read, the files
+/// are not validated otherwise.
+pub fn read(dir: &Path) -> io::Result<Labels> {
+    let mut labels = Labels::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("xml") {
+            continue;
+        }
+
+        let xml = fs::read_to_string(&path)?;
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} in {:?}", what, path),
+            )
+        };
+
+        let filename = element(&xml, "filename").ok_or_else(|| invalid("No filename"))?;
+        let boxes = labels.entry(unescape(filename.trim())).or_default();
+
+        let mut rest: &str = &xml;
+        while let Some(object) = element(rest, "object") {
+            let bndbox = element(object, "bndbox").ok_or_else(|| invalid("No bndbox"))?;
+            let coordinate = |tag: &str| -> io::Result<u32> {
+                element(bndbox, tag)
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                    .map(|value| value.max(1.0).round() as u32)
+                    .ok_or_else(|| invalid(&format!("Invalid {}", tag)))
+            };
+
+            // Coordinates start at 1 and include both ends of the box.
+            let (xmin, ymin) = (coordinate("xmin")?, coordinate("ymin")?);
+            let (xmax, ymax) = (coordinate("xmax")?, coordinate("ymax")?);
+            boxes.push(Rect {
+                x: xmin - 1,
+                y: ymin - 1,
+                width: (xmax + 1).saturating_sub(xmin),
+                height: (ymax + 1).saturating_sub(ymin),
+            });
+
+            let end = rest.find("</object>").expect("Element was found") + "</object>".len();
+            rest = &rest[end..];
+        }
+    }
+
+    Ok(labels)
+}
+
+/// Text between the first opening and the following closing tag of given
+/// element.
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(&xml[start..end])
+}
+
+/// Reverts what `escape` does.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::metadata::frame_with_rects;
+    use super::super::scratch::Scratch;
+    use super::*;
+
+    fn rects() -> Vec<Rect> {
+        vec![
+            Rect {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 20,
+            },
+            Rect {
+                x: 100,
+                y: 50,
+                width: 1,
+                height: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let scratch = Scratch::new();
+        let frames = vec![
+            frame_with_rects("a & b.png", &rects()),
+            frame_with_rects("c.png", &[]),
+        ];
+
+        write(&frames, "dir", &scratch).unwrap();
+        let labels = read(&scratch).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["a & b.png"], rects());
+        assert!(labels["c.png"].is_empty());
+    }
+}
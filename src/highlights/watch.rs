@@ -0,0 +1,88 @@
+use super::heat_map::validate_cell_size;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// Name of the file with the passes of a directory in its output directory.
+pub const WATCH: &str = "watch.json";
+
+/// Cell sizes of the passes over a directory if the schedule is not
+/// configured. Each pass focuses deeper than the one before.
+pub const REWATCH_SCHEDULE: [u32; 4] = [14, 12, 10, 8];
+
+/// How many times a directory was re-watched and with which cell sizes.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Watch {
+    /// Passes over the directory, oldest first.
+    pub passes: Vec<Pass>,
+}
+
+/// Run of a directory which was started by a re-watch. Runs started otherwise
+/// are passes too, but are not recorded.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pass {
+    /// Number of the pass, which is the number of the runs of the directory
+    /// including this one.
+    pub pass: usize,
+
+    pub run: String,
+    pub cell_size: u32,
+
+    /// Number of images sent to the worker.
+    pub frames: usize,
+
+    /// When the pass was started, in seconds since the Unix epoch.
+    pub created: u64,
+}
+
+/// Cell size of the pass over a directory which already has given number of
+/// runs. Once the schedule runs out, the passes stay at its last cell size.
+pub fn scheduled_cell_size(schedule: &[u32], runs: usize) -> u32 {
+    schedule[runs.min(schedule.len() - 1)]
+}
+
+/// Reads the passes over the directory. Directories which were never
+/// re-watched have none.
+pub fn read_watch(output_dir: &Path) -> io::Result<Watch> {
+    let path = output_dir.join(WATCH);
+    if !path.is_file() {
+        return Ok(Watch::default());
+    }
+
+    Ok(serde_json::from_reader(File::open(path)?)?)
+}
+
+pub fn record_watch(output_dir: &Path, watch: &Watch) -> io::Result<()> {
+    let path = output_dir.join(WATCH);
+    let temporary = output_dir.join(format!("{}.tmp", WATCH));
+
+    serde_json::to_writer_pretty(File::create(&temporary)?, watch)?;
+    fs::rename(temporary, path)
+}
+
+/// Checks the cell sizes of a schedule. Returns the reason why the passes
+/// could not run with them.
+pub fn validate_schedule(schedule: &[u32]) -> Result<(), String> {
+    if schedule.is_empty() {
+        return Err("Schedule of the passes is empty.".to_string());
+    }
+
+    schedule
+        .iter()
+        .try_for_each(|size| validate_cell_size(*size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_follow_schedule() {
+        let cell_sizes: Vec<u32> = (0..6)
+            .map(|runs| scheduled_cell_size(&REWATCH_SCHEDULE, runs))
+            .collect();
+
+        assert_eq!(cell_sizes, vec![14, 12, 10, 8, 8, 8]);
+    }
+}
@@ -0,0 +1,243 @@
+use super::helpers::pixel_value;
+use super::point::Point;
+use super::visual_object::VisualObject;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+type PointMap = Vec<Vec<bool>>;
+type DistanceMap = Vec<Vec<u32>>;
+
+/// Labels of cells where 0 means the cell has not been assigned to any basin.
+type LabelMap = Vec<Vec<usize>>;
+
+/// Splits the object along its narrowest necks. Distance of each cell to the
+/// nearest cell outside of the object is calculated. The object is then eroded
+/// level by level until it falls apart into at least two cores. The cores are
+/// used as markers from which the object is flooded back, starting with the
+/// cells furthest from the border. Every cell of the original object ends up in
+/// exactly one of the children.
+///
+/// Returns None if the object has no neck to be split along, e.g. if it is a
+/// solid blob.
+pub fn split_by_watershed(object: &mut VisualObject) -> Option<Vec<VisualObject>> {
+    let map = object.point_map()?;
+    let (lower, _) = object.size()?;
+    let distances = distance_transform(&map);
+    let deepest = distances
+        .iter()
+        .flat_map(|row| row.iter())
+        .cloned()
+        .max()
+        .unwrap_or(0);
+
+    for level in 1..deepest {
+        let (cores, count) = label_components(&map, |x, y| distances[y][x] > level);
+
+        if count < 2 {
+            continue;
+        }
+
+        let labels = flood_from_markers(&map, &distances, cores);
+
+        let mut children: Vec<VisualObject> = (0..count)
+            .map(|_| VisualObject::new(object.reference))
+            .collect();
+        for (y, row) in labels.iter().enumerate() {
+            for (x, label) in row.iter().enumerate() {
+                if *label != 0 {
+                    children[*label - 1].push(lower + Point::new(x as u32, y as u32));
+                }
+            }
+        }
+
+        return Some(children);
+    }
+
+    None
+}
+
+/// Calculates the chessboard distance of each highlighted cell to the closest
+/// cell which is not highlighted. Cells outside of the map are considered not
+/// highlighted, therefore cells on the border of the map have distance of 1.
+fn distance_transform(map: &PointMap) -> DistanceMap {
+    let mut distances: DistanceMap = map.iter().map(|row| vec![0; row.len()]).collect();
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+
+    // Seeds the search with the cells on the border of the object.
+    for (y, row) in map.iter().enumerate() {
+        for (x, highlighted) in row.iter().enumerate() {
+            if *highlighted
+                && moore_neighbours(x, y).any(|(nx, ny)| !pixel_value(map, nx, ny, false))
+            {
+                distances[y][x] = 1;
+                queue.push_back((x, y));
+            }
+        }
+    }
+
+    // Breadth first search guarantees that each cell is reached for the first
+    // time from the closest border.
+    while let Some((x, y)) = queue.pop_front() {
+        let distance = distances[y][x];
+
+        for (nx, ny) in moore_neighbours(x, y) {
+            if pixel_value(map, nx, ny, false) && distances[ny as usize][nx as usize] == 0 {
+                distances[ny as usize][nx as usize] = distance + 1;
+                queue.push_back((nx as usize, ny as usize));
+            }
+        }
+    }
+
+    distances
+}
+
+/// Labels the connected components of cells which satisfy given predicate. The
+/// cells are connected through their Moore neighbourhood, which is the same
+/// rule the flood fill uses when extracting the objects.
+fn label_components<F>(map: &PointMap, predicate: F) -> (LabelMap, usize)
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut labels: LabelMap = map.iter().map(|row| vec![0; row.len()]).collect();
+    let mut count = 0;
+
+    for (y, row) in map.iter().enumerate() {
+        for x in 0..row.len() {
+            if labels[y][x] != 0 || !predicate(x, y) {
+                continue;
+            }
+
+            count += 1;
+            labels[y][x] = count;
+            let mut stack = vec![(x, y)];
+
+            while let Some((cx, cy)) = stack.pop() {
+                for (nx, ny) in moore_neighbours(cx, cy) {
+                    if !pixel_value(map, nx, ny, false) {
+                        continue;
+                    }
+
+                    let (nx, ny) = (nx as usize, ny as usize);
+                    if labels[ny][nx] == 0 && predicate(nx, ny) {
+                        labels[ny][nx] = count;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+    }
+
+    (labels, count)
+}
+
+/// Grows the markers into the rest of the object. Cells that are further from
+/// the border of the object are flooded first, therefore two basins meet at the
+/// narrowest part of the object. Ties are resolved in the order the cells were
+/// reached so that the result is deterministic.
+fn flood_from_markers(map: &PointMap, distances: &DistanceMap, mut labels: LabelMap) -> LabelMap {
+    let mut heap: BinaryHeap<(u32, Reverse<usize>, usize, usize)> = BinaryHeap::new();
+    let mut order = 0;
+
+    for (y, row) in labels.iter().enumerate() {
+        for (x, label) in row.iter().enumerate() {
+            if *label != 0 {
+                heap.push((distances[y][x], Reverse(order), x, y));
+                order += 1;
+            }
+        }
+    }
+
+    while let Some((_, _, x, y)) = heap.pop() {
+        let label = labels[y][x];
+
+        for (nx, ny) in moore_neighbours(x, y) {
+            if !pixel_value(map, nx, ny, false) {
+                continue;
+            }
+
+            let (nx, ny) = (nx as usize, ny as usize);
+            if labels[ny][nx] == 0 {
+                labels[ny][nx] = label;
+                heap.push((distances[ny][nx], Reverse(order), nx, ny));
+                order += 1;
+            }
+        }
+    }
+
+    labels
+}
+
+/// Iterates over the coordinates of the Moore neighbourhood of given cell. The
+/// coordinates can be out of bounds of the map.
+fn moore_neighbours(x: usize, y: usize) -> impl Iterator<Item = (isize, isize)> {
+    let (x, y) = (x as isize, y as isize);
+
+    (-1..2)
+        .flat_map(move |dy| (-1..2).map(move |dx| (dx, dy)))
+        .filter(|&(dx, dy)| dx != 0 || dy != 0)
+        .map(move |(dx, dy)| (x + dx, y + dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(cells: &[(u32, u32)]) -> VisualObject {
+        let mut object = VisualObject::new(Point::new(0, 0));
+        for (x, y) in cells {
+            object.push(Point::new(*x, *y));
+        }
+        object
+    }
+
+    fn square(x: u32, y: u32, size: u32) -> Vec<(u32, u32)> {
+        (y..y + size)
+            .flat_map(|y| (x..x + size).map(move |x| (x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn distance_to_border() {
+        let map: PointMap = vec![vec![true; 5]; 5];
+        let distances = distance_transform(&map);
+
+        assert_eq!(distances[0], vec![1, 1, 1, 1, 1]);
+        assert_eq!(distances[1], vec![1, 2, 2, 2, 1]);
+        assert_eq!(distances[2], vec![1, 2, 3, 2, 1]);
+    }
+
+    #[test]
+    fn splits_along_neck() {
+        // Two squares joined by a bridge one cell thick.
+        let mut cells = square(0, 0, 7);
+        cells.extend(square(10, 0, 7));
+        cells.extend((7..10).map(|x| (x, 3)));
+        let mut dumbbell = object(&cells);
+
+        let mut children = split_by_watershed(&mut dumbbell).expect("Dumbbell has a neck");
+        assert_eq!(children.len(), 2);
+
+        // Every cell ends up in exactly one child, each child has one square.
+        let mut split: Vec<(u32, u32)> = children
+            .iter()
+            .flat_map(|child| child.points.iter().map(|p| (p.x, p.y)))
+            .collect();
+        split.sort();
+        cells.sort();
+        assert_eq!(split, cells);
+
+        let mut bounds: Vec<(Point, Point)> = children
+            .iter_mut()
+            .map(|child| child.size().unwrap())
+            .collect();
+        bounds.sort_by_key(|(lower, _)| lower.x);
+        assert!(bounds[0].0 == Point::new(0, 0) && bounds[0].1.x < 10);
+        assert!(bounds[1].0.x > 6 && bounds[1].1 == Point::new(16, 6));
+    }
+
+    #[test]
+    fn solid_blob_is_not_split() {
+        assert!(split_by_watershed(&mut object(&square(0, 0, 7))).is_none());
+        assert!(split_by_watershed(&mut object(&[(0, 0)])).is_none());
+    }
+}
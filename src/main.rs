@@ -1,41 +1,106 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
+extern crate base64;
 extern crate dotenv;
 extern crate rayon;
 extern crate rocket_contrib;
 extern crate serde;
+extern crate serde_json;
+extern crate tar;
 #[macro_use]
 extern crate rocket;
 
+mod cli;
 mod conf;
+mod gallery;
 mod highlights;
+mod multipart;
 mod routes;
+mod watcher;
 
 use dotenv::dotenv;
 use rayon::ThreadPoolBuilder;
-use std::path::Path;
+use std::env;
+use std::path::PathBuf;
+use std::process;
 use std::sync::mpsc::channel;
-use std::sync::Mutex;
+use std::sync::Arc;
 use std::thread;
 
 fn main() {
+    // Subcommands run once instead of the service.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("evaluate") {
+        if let Err(error) = cli::evaluate(&args[1..]) {
+            eprintln!("{}\n\n{}", error, cli::EVALUATE_USAGE);
+            process::exit(1);
+        }
+        return;
+    }
+
     dotenv().ok();
     let conf = conf::ServerConf::new();
 
     // Creates a channel between the worker and the web server.
-    let (producer, consumer) = channel::<Box<Path>>();
+    let (producer, consumer) = channel::<highlights::Task>();
 
-    // Prepares new thread pool for the worker to delegate jobs to.
+    // Prepares new thread pool for the worker to delegate jobs to. Sweeps run
+    // on it as well.
     let pool = ThreadPoolBuilder::new()
         .num_threads(conf.worker_threads())
+        // Frames catch their own panics, this only keeps a panic elsewhere in
+        // a task from aborting the service.
+        .panic_handler(|_| println!("[Worker] A task panicked."))
         .build()
         .expect("Couldn't build worker threadpool");
+    let pool = Arc::new(pool);
+    let sweeper = highlights::Sweeper::new(Arc::clone(&pool));
+
+    // The queue is shared by the routes and the watcher of the input root.
+    let queue = Arc::new(highlights::Queue::new(
+        producer,
+        PathBuf::from(conf.input_path()),
+        PathBuf::from(conf.output_path()),
+    ));
+
+    // Frames which were queued before the service restarted are queued again.
+    queue.replay();
 
     thread::spawn(move || highlights::listen(consumer, pool));
 
+    if let Some(watcher) = conf.watcher().cloned() {
+        let queue = Arc::clone(&queue);
+        let input_root = PathBuf::from(conf.input_path());
+        let output_root = PathBuf::from(conf.output_path());
+        thread::spawn(move || watcher::watch(watcher, &input_root, &output_root, queue));
+    }
+
     rocket::ignite()
-        .mount("/highlights", routes![routes::find_highlights])
+        .mount(
+            "/highlights",
+            routes![
+                routes::find_highlights,
+                routes::rewatch,
+                routes::export_highlights,
+                routes::sweep_parameters,
+                routes::get_sweep,
+                routes::find_highlights_in_image,
+                routes::list_directories,
+                routes::list_frames,
+                routes::list_runs,
+                routes::diff_runs,
+                routes::get_highlight,
+                routes::get_original,
+                routes::post_feedback,
+                routes::get_feedback
+            ],
+        )
+        .mount(
+            "/gallery",
+            routes![gallery::index, gallery::script, gallery::style],
+        )
         .manage(conf)
-        .manage(Mutex::new(producer))
+        .manage(queue)
+        .manage(sweeper)
         .launch();
 }
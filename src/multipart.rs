@@ -0,0 +1,168 @@
+/// Single part of a `multipart/form-data` body.
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    /// Name of the form field.
+    pub name: String,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The body does not follow the format, e.g. it ends before the closing
+    /// delimiter.
+    Malformed,
+    /// A part is larger than the limit of its field.
+    TooLarge(String),
+}
+
+/// Splits the body into its parts. Only the name of each part is read from its
+/// headers, the rest is ignored. The data of each part has to fit the limit
+/// given for its name, and the body has to end with the closing delimiter.
+pub fn parse<'a, F>(body: &'a [u8], boundary: &str, limit: F) -> Result<Vec<Part<'a>>, Error>
+where
+    F: Fn(&str) -> usize,
+{
+    let delimiter = format!("\r\n--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // The first delimiter does not have to be preceded by a line break.
+    let mut position =
+        find(body, &delimiter[2..], 0).ok_or(Error::Malformed)? + delimiter.len() - 2;
+    let mut parts: Vec<Part> = Vec::new();
+
+    loop {
+        // The last delimiter is followed by two dashes.
+        if body[position..].starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        let headers_start = find(body, b"\r\n", position).ok_or(Error::Malformed)? + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start).ok_or(Error::Malformed)?;
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let name = name(&headers).ok_or(Error::Malformed)?;
+
+        let data_start = headers_end + 4;
+        let data_end = find(body, delimiter, data_start).ok_or(Error::Malformed)?;
+        if data_end - data_start > limit(&name) {
+            return Err(Error::TooLarge(name));
+        }
+
+        parts.push(Part {
+            name,
+            data: &body[data_start..data_end],
+        });
+
+        position = data_end + delimiter.len();
+    }
+}
+
+/// Reads the name of the field from the content disposition header.
+fn name(headers: &str) -> Option<String> {
+    let disposition = headers
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("content-disposition:"))?;
+
+    disposition.split(';').find_map(|parameter| {
+        let (key, value) = parameter.trim().split_at(parameter.trim().find('=')?);
+        if key == "name" {
+            Some(value[1..].trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Position of the first occurrence of the needle at or after given position.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "XyZ";
+
+    fn unlimited(_: &str) -> usize {
+        usize::MAX
+    }
+
+    #[test]
+    fn splits_parts() {
+        let body: &[u8] = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"settings\"\r\n\r\n\
+            {}\r\n--XyZ\r\n\
+            content-disposition: form-data; name=\"image\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            \x89PNG\r\n--Xy\r\n\r\n\x00\r\n--XyZ--\r\n";
+
+        let parts = parse(body, BOUNDARY, unlimited).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "settings");
+        assert_eq!(parts[0].data, b"{}");
+        assert_eq!(parts[1].name, "image");
+        assert_eq!(parts[1].data, &b"\x89PNG\r\n--Xy\r\n\r\n\x00"[..]);
+    }
+
+    #[test]
+    fn body_without_parts_is_empty() {
+        assert_eq!(parse(b"--XyZ--\r\n", BOUNDARY, unlimited).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn malformed_bodies_are_rejected() {
+        // No delimiter, unterminated part, missing name and missing headers.
+        assert_eq!(parse(b"", BOUNDARY, unlimited), Err(Error::Malformed));
+        assert_eq!(
+            parse(b"--Other\r\n\r\n\r\n--Other--", BOUNDARY, unlimited),
+            Err(Error::Malformed)
+        );
+        assert!(parse(
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata",
+            BOUNDARY,
+            unlimited
+        )
+        .is_err());
+        assert!(parse(
+            b"--XyZ\r\nContent-Disposition: form-data\r\n\r\ndata\r\n--XyZ--",
+            BOUNDARY,
+            unlimited
+        )
+        .is_err());
+        assert!(parse(b"--XyZ\r\ndata\r\n--XyZ--", BOUNDARY, unlimited).is_err());
+    }
+
+    #[test]
+    fn missing_closing_delimiter_is_rejected() {
+        let part = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata\r\n--XyZ";
+        assert_eq!(parse(part, BOUNDARY, unlimited), Err(Error::Malformed));
+
+        let mut next = part.to_vec();
+        next.extend(b"\r\n");
+        assert_eq!(parse(&next, BOUNDARY, unlimited), Err(Error::Malformed));
+
+        let mut closed = part.to_vec();
+        closed.extend(b"--");
+        assert_eq!(parse(&closed, BOUNDARY, unlimited).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parts_larger_than_their_limit_are_rejected() {
+        let body: &[u8] = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"settings\"\r\n\r\n\
+            {}\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"image\"\r\n\r\n\
+            12345\r\n--XyZ--";
+
+        let limit = |image: usize| move |name: &str| if name == "image" { image } else { 2 };
+        assert_eq!(parse(body, BOUNDARY, limit(5)).unwrap().len(), 2);
+        assert_eq!(
+            parse(body, BOUNDARY, limit(4)),
+            Err(Error::TooLarge("image".to_string()))
+        );
+    }
+}
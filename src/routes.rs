@@ -1,78 +1,573 @@
 use conf::ServerConf;
-use rocket::http::Status;
-use rocket::State;
+use highlights::{
+    self, is_valid_name, DirectorySummary, ExportFormat, Feedback, FeedbackEntry, FrameMetadata,
+    InlineHighlights, Mode, Pass, Queue, Rect, RunDiff, RunSummary, Settings, SubmitError,
+    Submitted, Sweep, Sweeper, SWEEP_REPORT,
+};
+use multipart;
+use rocket::http::{ContentType, Status};
+use rocket::response::content::Content;
+use rocket::response::{status, NamedFile};
+use rocket::{Data, State};
 use rocket_contrib::json::Json;
 use serde::Deserialize;
-use std::fs;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::Sender;
-use std::sync::Mutex;
+use std::sync::Arc;
+
+/// Largest image which can be sent to the synchronous endpoint.
+const MAX_IMAGE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Largest settings which can be sent along with an image to the synchronous
+/// endpoint.
+const MAX_SETTINGS_BYTES: usize = 64 * 1024;
+
+/// Largest number of pixels of an image sent to the synchronous endpoint. A
+/// small compressed image can still decode to a huge one.
+const MAX_IMAGE_PIXELS: u64 = 40 * 1000 * 1000;
 
 #[derive(Deserialize)]
 pub struct DirectoryToProcess {
     // Name of the directory on shared volume that contains images which should be processed.
     name: String,
+
+    // Overrides the default parameters of the pipeline for all images in the
+    // directory.
+    #[serde(default)]
+    settings: Option<Settings>,
+
+    // What to do with a previous run of the directory. Requests without a mode
+    // create a new run.
+    #[serde(default)]
+    mode: Option<Mode>,
+
+    // Run which the mode applies to, the latest one by default.
+    #[serde(default)]
+    run: Option<String>,
 }
 
 #[post("/", format = "application/json", data = "<req>")]
 pub fn find_highlights(
-    conf: State<ServerConf>,
-    producer: State<Mutex<Sender<Box<Path>>>>,
+    queue: State<Arc<Queue>>,
     req: Json<DirectoryToProcess>,
-) -> Result<Status, Status> {
+) -> Result<status::Accepted<Json<Submitted>>, Status> {
     let data_directory = &req.name;
 
-    if !data_directory
-        .chars()
-        .all(|c| char::is_alphanumeric(c) || c == '_')
-    {
+    if !is_valid_name(data_directory) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    if let Some(run) = &req.run {
+        if req.mode.is_none() || !is_valid_name(run) {
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    if let Some(Err(error)) = req.settings.as_ref().map(Settings::validate) {
+        println!("Invalid settings: {}", error);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let submitted = queue
+        .submit(
+            data_directory,
+            req.settings.clone(),
+            req.mode,
+            req.run.as_deref(),
+        )
+        .map_err(|error| submit_status(data_directory, error))?;
+    println!(
+        "Queued {} frames of {}/{}.",
+        submitted.frames, data_directory, submitted.run
+    );
+
+    Ok(status::Accepted(Some(Json(submitted))))
+}
+
+/// Processes the directory again as a new run with the next cell size of the
+/// re-watch schedule, so that each pass focuses deeper than the one before.
+#[post("/<name>/rewatch")]
+pub fn rewatch(
+    conf: State<ServerConf>,
+    queue: State<Arc<Queue>>,
+    name: String,
+) -> Result<status::Accepted<Json<Pass>>, Status> {
+    if !is_valid_name(&name) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let pass = queue
+        .rewatch(&name, conf.rewatch_schedule())
+        .map_err(|error| submit_status(&name, error))?;
+    println!(
+        "Queued {} frames of {}/{} as pass {} with cell size {}.",
+        pass.frames, name, pass.run, pass.pass, pass.cell_size
+    );
+
+    Ok(status::Accepted(Some(Json(pass))))
+}
+
+/// Logs why the images of the directory could not be submitted.
+fn submit_status(name: &str, error: SubmitError) -> Status {
+    match error {
+        SubmitError::NotFound => {
+            println!("Directory {} or its run does not exist.", name);
+            Status::NotFound
+        }
+        SubmitError::Conflict(reason) => {
+            println!("{}", reason);
+            Status::Conflict
+        }
+        SubmitError::Io(io_error) => {
+            println!("Cannot submit {}: {}.", name, io_error);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Exports the highlights of a run of an already processed directory into
+/// standard annotation formats or arrays. Frames which are still being
+/// processed are not included.
+#[post("/<name>/export?<run>", format = "application/json", data = "<req>")]
+pub fn export_highlights(
+    conf: State<ServerConf>,
+    name: String,
+    run: Option<String>,
+    req: Json<ExportFormat>,
+) -> Result<Status, Status> {
+    let output_path = processed_run(&conf, &name, run)?;
+
+    let path = highlights::export(&output_path, req.into_inner()).map_err(|io_error| {
+        println!("Cannot export {:?}: {}.", &output_path, io_error);
+        match io_error.kind() {
+            io::ErrorKind::InvalidData => Status::UnprocessableEntity,
+            _ => Status::InternalServerError,
+        }
+    })?;
+    println!("Exported {:?} to {:?}.", &output_path, path);
+
+    Ok(Status::Created)
+}
+
+/// Runs the pipeline on a sample of the images of the directory with each
+/// combination of given parameters on the worker pool. The report is read with
+/// `GET` once the sweep ends. If the request has a target, the best parameters
+/// become the defaults of the directory, which are used by requests without
+/// settings.
+#[post("/<name>/sweep", format = "application/json", data = "<req>")]
+pub fn sweep_parameters(
+    conf: State<ServerConf>,
+    sweeper: State<Sweeper>,
+    name: String,
+    req: Json<Sweep>,
+) -> Result<status::Accepted<()>, Status> {
+    if !is_valid_name(&name) {
         return Err(Status::UnprocessableEntity);
     }
 
-    let input_path: PathBuf = [conf.input_path(), data_directory].iter().collect();
+    if let Err(error) = req.validate() {
+        println!("Invalid sweep: {}", error);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let input_path: PathBuf = [conf.input_path(), &name].iter().collect();
     if !input_path.is_dir() {
-        println!("Path {:?} is not a directory.", &input_path);
+        println!("Directory {} does not exist.", name);
         return Err(Status::NotFound);
     }
 
-    let output_path: PathBuf = [conf.output_path(), data_directory].iter().collect();
-    if output_path.is_dir() {
-        println!("Path {:?} already processed.", &output_path);
+    let output_path: PathBuf = [conf.output_path(), &name].iter().collect();
+    match sweeper.start(&name, input_path, output_path, req.into_inner()) {
+        Ok(true) => {
+            println!("Sweeping {}.", name);
+            Ok(status::Accepted(None))
+        }
+        Ok(false) => {
+            println!("Directory {} is being swept already.", name);
+            Err(Status::Conflict)
+        }
+        Err(io_error) => {
+            println!("Cannot sweep {}: {}.", name, io_error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Report of the last sweep of the directory, or the reason why it failed.
+/// While the directory is being swept, the response is `202`.
+#[get("/<name>/sweep")]
+pub fn get_sweep(
+    conf: State<ServerConf>,
+    sweeper: State<Sweeper>,
+    name: String,
+) -> Result<Content<NamedFile>, Status> {
+    if !is_valid_name(&name) {
         return Err(Status::UnprocessableEntity);
-    } else {
-        fs::create_dir(output_path).map_err(|io_error| {
-            println!("Cannot create output dir: {}.", io_error);
-            Status::UnprocessableEntity
-        })?;
-    }
-
-    // If the requested directory does not exist on file system, convert the error to
-    // 404 http status.
-    let items = fs::read_dir(input_path).map_err(|_| Status::NotFound)?;
-
-    // Iterate through each result and consider files only.
-    let items: Vec<Box<Path>> = items
-        .into_iter()
-        .filter_map(|result| result.ok().map(|item| item.path()))
-        .filter(|item| item.is_file())
-        .map(|file| file.into_boxed_path())
-        .collect();
-
-    // We acquire a mutex lock, clone the producer and immediately drop the lock.
-    let producer = {
-        producer
-            .lock()
-            .map(|original| original.clone())
-            .map_err(|_| Status::InternalServerError)?
+    }
+
+    if sweeper.is_running(&name) {
+        return Err(Status::Accepted);
+    }
+
+    let path: PathBuf = [conf.output_path(), &name, SWEEP_REPORT].iter().collect();
+    NamedFile::open(path)
+        .map(|file| Content(ContentType::JSON, file))
+        .map_err(|_| Status::NotFound)
+}
+
+/// Finds highlights in a single image sent in the body and returns them right
+/// away. Nothing is persisted and the worker queue is not involved.
+///
+/// The body is either the encoded image itself, or a multipart form with the
+/// image in the `image` field and optionally the settings as JSON in the
+/// `settings` field. Crops and images of the stages of the pipeline are only
+/// returned if asked for in the query.
+#[post("/image?<crops>&<debug>", data = "<data>")]
+pub fn find_highlights_in_image(
+    content_type: Option<&ContentType>,
+    crops: Option<bool>,
+    debug: Option<bool>,
+    data: Data,
+) -> Result<Json<InlineHighlights>, Status> {
+    let mut body: Vec<u8> = Vec::new();
+    data.open()
+        .take(MAX_IMAGE_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|_| Status::BadRequest)?;
+    if body.len() as u64 > MAX_IMAGE_BYTES {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let boundary = content_type
+        .filter(|content_type| content_type.is_form_data())
+        .and_then(|content_type| content_type.params().find(|(key, _)| *key == "boundary"))
+        .map(|(_, boundary)| boundary.to_string());
+
+    let (image, settings): (&[u8], Settings) = match boundary {
+        None => (&body, Settings::default()),
+        Some(boundary) => {
+            let limit = |name: &str| match name {
+                "settings" => MAX_SETTINGS_BYTES,
+                _ => MAX_IMAGE_BYTES as usize,
+            };
+            let parts = multipart::parse(&body, &boundary, limit).map_err(|error| match error {
+                multipart::Error::Malformed => Status::BadRequest,
+                multipart::Error::TooLarge(name) => {
+                    println!("Field {} is too large.", name);
+                    Status::PayloadTooLarge
+                }
+            })?;
+            let image = parts
+                .iter()
+                .find(|part| part.name == "image")
+                .ok_or(Status::UnprocessableEntity)?;
+            let settings = match parts.iter().find(|part| part.name == "settings") {
+                None => Settings::default(),
+                Some(part) => serde_json::from_slice(part.data).map_err(|error| {
+                    println!("Invalid settings: {}", error);
+                    Status::UnprocessableEntity
+                })?,
+            };
+
+            (image.data, settings)
+        }
     };
 
-    // We send each image path as one message. This helps the worker distribute
-    // the workload into the threadpool.
-    for item in items.into_iter() {
-        producer
-            .send(item)
-            .map_err(|_| Status::ServiceUnavailable)?;
+    if let Err(error) = settings.validate() {
+        println!("Invalid settings: {}", error);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let (width, height) = highlights::image_dimensions(image).map_err(|error| {
+        println!("Cannot read image: {}", error);
+        Status::UnprocessableEntity
+    })?;
+    if width * height > MAX_IMAGE_PIXELS {
+        println!("Image of {}x{} pixels is too large.", width, height);
+        return Err(Status::PayloadTooLarge);
+    }
+    // Smaller images do not have a single cell in the heat map.
+    if width.min(height) < u64::from(settings.smallest_cell_size()) {
+        println!(
+            "Image of {}x{} pixels is smaller than a cell.",
+            width, height
+        );
+        return Err(Status::UnprocessableEntity);
+    }
+
+    highlights::identify_inline(
+        image,
+        &settings,
+        crops.unwrap_or(false),
+        debug.unwrap_or(false),
+    )
+    .map(Json)
+    .map_err(|error| {
+        println!("Cannot process image: {}", error);
+        Status::UnprocessableEntity
+    })
+}
+
+/// Lists the processed directories with their runs and the number of frames
+/// and highlights of the latest run.
+#[get("/")]
+pub fn list_directories(conf: State<ServerConf>) -> Result<Json<Vec<DirectorySummary>>, Status> {
+    highlights::list_directories(Path::new(conf.output_path()), is_valid_name)
+        .map(Json)
+        .map_err(|io_error| {
+            println!("Cannot list output directories: {}.", io_error);
+            Status::InternalServerError
+        })
+}
+
+/// Returns the metadata of all processed frames of a run of the directory.
+#[get("/<name>?<run>")]
+pub fn list_frames(
+    conf: State<ServerConf>,
+    name: String,
+    run: Option<String>,
+) -> Result<Json<Vec<FrameMetadata>>, Status> {
+    let output_path = processed_run(&conf, &name, run)?;
+
+    highlights::read_frames(&output_path)
+        .map(Json)
+        .map_err(|io_error| {
+            println!("Cannot read frames of {:?}: {}.", &output_path, io_error);
+            Status::InternalServerError
+        })
+}
+
+/// Lists the runs of the directory with the parameters each was processed with,
+/// oldest first.
+#[get("/<name>/runs")]
+pub fn list_runs(conf: State<ServerConf>, name: String) -> Result<Json<Vec<RunSummary>>, Status> {
+    let output_path = processed_directory(&conf, &name)?;
+
+    highlights::summarize_runs(&output_path)
+        .map(Json)
+        .map_err(|io_error| {
+            println!("Cannot list runs of {:?}: {}.", &output_path, io_error);
+            Status::InternalServerError
+        })
+}
+
+/// Compares the number of highlights of each frame in two runs of the
+/// directory. Without the query, the two latest runs are compared.
+#[get("/<name>/runs/diff?<a>&<b>")]
+pub fn diff_runs(
+    conf: State<ServerConf>,
+    name: String,
+    a: Option<String>,
+    b: Option<String>,
+) -> Result<Json<RunDiff>, Status> {
+    let output_path = processed_directory(&conf, &name)?;
+    let runs = highlights::list_runs(&output_path).map_err(|io_error| {
+        println!("Cannot list runs of {:?}: {}.", &output_path, io_error);
+        Status::InternalServerError
+    })?;
+
+    let mut newest = runs.iter().rev().cloned();
+    let (latest, previous) = (newest.next(), newest.next());
+    let (a, b) = match (a.or(previous), b.or(latest)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Err(Status::NotFound),
+    };
+    if !runs.contains(&a) || !runs.contains(&b) {
+        return Err(Status::NotFound);
+    }
+
+    highlights::diff_runs(&output_path, &a, &b)
+        .map(Json)
+        .map_err(|io_error| {
+            println!("Cannot compare runs of {:?}: {}.", &output_path, io_error);
+            Status::InternalServerError
+        })
+}
+
+/// Serves the highlight with given index of the frame. The frame is the file
+/// name of the original image without extension.
+#[get("/<name>/<frame>/<index>?<run>", rank = 2)]
+pub fn get_highlight(
+    conf: State<ServerConf>,
+    name: String,
+    frame: String,
+    index: usize,
+    run: Option<String>,
+) -> Result<Content<Vec<u8>>, Status> {
+    let output_path = processed_run(&conf, &name, run)?;
+    if !is_valid_name(&frame) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let (file, bytes) = highlights::read_highlight(&output_path, &frame, index)
+        .map_err(|io_error| {
+            println!(
+                "Cannot read highlight {}/{}/{}: {}.",
+                name, frame, index, io_error
+            );
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    let content_type = Path::new(&file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+
+    Ok(Content(content_type, bytes))
+}
+
+/// Serves the original image of a processed frame from the input directory, so
+/// that the highlights can be shown on top of it.
+#[get("/<name>/<frame>/original?<run>")]
+pub fn get_original(
+    conf: State<ServerConf>,
+    name: String,
+    frame: String,
+    run: Option<String>,
+) -> Result<NamedFile, Status> {
+    let output_path = processed_run(&conf, &name, run)?;
+    if !is_valid_name(&frame) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let metadata = highlights::read_frame(&output_path, &frame)
+        .map_err(|io_error| {
+            println!("Cannot read frame {}/{}: {}.", name, frame, io_error);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    let input_path: PathBuf = [conf.input_path(), &name, &metadata.frame].iter().collect();
+    NamedFile::open(input_path).map_err(|_| Status::NotFound)
+}
+
+/// Records whether a person accepts or rejects the highlight, optionally with
+/// the rectangle it should have had.
+#[post(
+    "/<name>/<frame>/<index>/feedback?<run>",
+    format = "application/json",
+    data = "<req>"
+)]
+pub fn post_feedback(
+    conf: State<ServerConf>,
+    name: String,
+    frame: String,
+    index: usize,
+    run: Option<String>,
+    req: Json<Feedback>,
+) -> Result<Status, Status> {
+    let output_path = processed_highlight(&conf, &name, run, &frame, index, req.rect)?;
+
+    let entry = FeedbackEntry::new(&frame, index, req.into_inner());
+    highlights::append_feedback(&output_path, &entry).map_err(|io_error| {
+        println!(
+            "Cannot persist feedback in {:?}: {}.",
+            &output_path, io_error
+        );
+        Status::InternalServerError
+    })?;
+
+    Ok(Status::Created)
+}
+
+/// Lists the feedback given to the highlight, oldest first.
+#[get("/<name>/<frame>/<index>/feedback?<run>")]
+pub fn get_feedback(
+    conf: State<ServerConf>,
+    name: String,
+    frame: String,
+    index: usize,
+    run: Option<String>,
+) -> Result<Json<Vec<FeedbackEntry>>, Status> {
+    let output_path = processed_highlight(&conf, &name, run, &frame, index, None)?;
+
+    highlights::read_feedback(&output_path, &frame, index)
+        .map(Json)
+        .map_err(|io_error| {
+            println!("Cannot read feedback in {:?}: {}.", &output_path, io_error);
+            Status::InternalServerError
+        })
+}
+
+/// Directory of a run in which the highlight with given index of the frame
+/// exists. The rectangle, if given, has to fit the frame.
+fn processed_highlight(
+    conf: &ServerConf,
+    name: &str,
+    run: Option<String>,
+    frame: &str,
+    index: usize,
+    rect: Option<Rect>,
+) -> Result<PathBuf, Status> {
+    let output_path = processed_run(conf, name, run)?;
+    if !is_valid_name(frame) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let metadata = highlights::read_frame(&output_path, frame)
+        .map_err(|io_error| {
+            println!("Cannot read frame {}/{}: {}.", name, frame, io_error);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    if index >= metadata.highlights.len() {
+        return Err(Status::NotFound);
+    }
+
+    if let Some(rect) = rect {
+        if rect.width == 0
+            || rect.height == 0
+            || rect.x.saturating_add(rect.width) > metadata.width
+            || rect.y.saturating_add(rect.height) > metadata.height
+        {
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    Ok(output_path)
+}
+
+/// Output directory of a request with given name which has to exist.
+fn processed_directory(conf: &ServerConf, name: &str) -> Result<PathBuf, Status> {
+    if !is_valid_name(name) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let output_path: PathBuf = [conf.output_path(), name].iter().collect();
+    if !output_path.is_dir() {
+        println!("Path {:?} was not processed.", &output_path);
+        return Err(Status::NotFound);
+    }
+
+    Ok(output_path)
+}
+
+/// Directory of given run of a processed directory, the latest run by default.
+/// The run has to exist.
+fn processed_run(conf: &ServerConf, name: &str, run: Option<String>) -> Result<PathBuf, Status> {
+    let output_path = processed_directory(conf, name)?;
+
+    let run = match run {
+        Some(run) => run,
+        None => highlights::latest_run(&output_path)
+            .map_err(|io_error| {
+                println!("Cannot list runs of {:?}: {}.", &output_path, io_error);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?,
+    };
+    if !is_valid_name(&run) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let run_path = output_path.join(run);
+    if !run_path.is_dir() {
+        println!("Run {:?} does not exist.", &run_path);
+        return Err(Status::NotFound);
     }
 
-    Ok(Status::Accepted)
+    Ok(run_path)
 }
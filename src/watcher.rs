@@ -0,0 +1,195 @@
+use conf::WatcherConf;
+use highlights::{self, is_image, is_valid_name, Queue};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Marker a producer of the frames can write into a directory once all of them
+/// are there, so that it is processed without waiting.
+const DONE_MARKER: &str = ".done";
+
+/// What the watcher needs to know about a directory to tell whether it is
+/// complete.
+#[derive(Debug)]
+struct Contents {
+    /// Whether the directory has the marker.
+    done: bool,
+
+    /// Number of images in the directory.
+    frames: usize,
+
+    /// Latest modification of the directory or any file in it.
+    modified: SystemTime,
+}
+
+/// Scans the input root for new directories forever and submits each of them
+/// once it is complete, the same way a request to process it would. A
+/// directory is new if it has no run yet.
+pub fn watch(conf: WatcherConf, input_root: &Path, output_root: &Path, queue: Arc<Queue>) {
+    println!(
+        "Watching directory {:?} for new directories every {} seconds.",
+        input_root,
+        conf.interval.as_secs()
+    );
+
+    // Directories which could not be inspected or submitted, with their latest
+    // modification at that time if it is known. Each failure is logged once
+    // and the directory is submitted again only once it changes.
+    let mut failures: HashMap<String, Option<SystemTime>> = HashMap::new();
+    loop {
+        if let Err(error) = scan(&conf, input_root, output_root, &queue, &mut failures) {
+            println!("Cannot scan {:?}: {}.", input_root, error);
+        }
+
+        thread::sleep(conf.interval);
+    }
+}
+
+/// Submits the new directories which are complete. A directory which cannot be
+/// inspected or submitted does not stop the scan of the others.
+fn scan(
+    conf: &WatcherConf,
+    input_root: &Path,
+    output_root: &Path,
+    queue: &Queue,
+    failures: &mut HashMap<String, Option<SystemTime>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(input_root)? {
+        let input_dir = match entry {
+            Ok(entry) => entry.path(),
+            Err(error) => {
+                println!("Cannot read an entry of {:?}: {}.", input_root, error);
+                continue;
+            }
+        };
+        let name = match input_dir.file_name().and_then(|name| name.to_str()) {
+            Some(name) if input_dir.is_dir() && is_valid_name(name) => name.to_string(),
+            _ => continue,
+        };
+
+        let contents = match highlights::list_runs(&output_root.join(&name))
+            .and_then(|runs| Ok((runs, inspect(&input_dir)?)))
+        {
+            Ok((ref runs, _)) if !runs.is_empty() => {
+                failures.remove(&name);
+                continue;
+            }
+            Ok((_, contents)) => contents,
+            Err(error) => {
+                if failures.insert(name.clone(), None).is_none() {
+                    println!("Cannot inspect directory {:?}: {}.", input_dir, error);
+                }
+                continue;
+            }
+        };
+
+        let unchanged = failures.get(&name) == Some(&Some(contents.modified));
+        if unchanged || !is_complete(&contents, conf.quiescence, SystemTime::now()) {
+            continue;
+        }
+
+        match queue.submit(&name, None, None, None) {
+            Ok(submitted) => {
+                failures.remove(&name);
+                println!(
+                    "Queued {} frames of new directory {}/{}.",
+                    submitted.frames, name, submitted.run
+                );
+            }
+            Err(error) => {
+                failures.insert(name.clone(), Some(contents.modified));
+                println!(
+                    "Cannot submit new directory {}: {:?}. It is submitted again once it changes.",
+                    name, error
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn inspect(input_dir: &Path) -> io::Result<Contents> {
+    let mut contents = Contents {
+        done: false,
+        frames: 0,
+        modified: fs::metadata(input_dir)?.modified()?,
+    };
+
+    for entry in fs::read_dir(input_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        // Other files being written, such as logs, also mean the producer is
+        // not done yet.
+        contents.modified = contents.modified.max(metadata.modified()?);
+        if entry.file_name() == DONE_MARKER {
+            contents.done = true;
+        } else if is_image(&entry.path()) {
+            contents.frames += 1;
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Whether the directory has the marker, or has some images and nothing in it
+/// was written for the quiescence period.
+fn is_complete(contents: &Contents, quiescence: Duration, now: SystemTime) -> bool {
+    // A clock which went backwards counts as a recent write.
+    let quiet = now
+        .duration_since(contents.modified)
+        .map_or(false, |elapsed| elapsed >= quiescence);
+    contents.done || (contents.frames > 0 && quiet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use highlights::scratch::Scratch;
+    use std::fs::File;
+
+    #[test]
+    fn complete_once_quiet_or_marked() {
+        let quiescence = Duration::from_secs(30);
+        let now = SystemTime::now();
+        let contents = |done, frames, ago| Contents {
+            done,
+            frames,
+            modified: now - Duration::from_secs(ago),
+        };
+
+        assert!(!is_complete(&contents(false, 3, 10), quiescence, now));
+        assert!(is_complete(&contents(false, 3, 30), quiescence, now));
+        assert!(!is_complete(&contents(false, 0, 60), quiescence, now));
+        assert!(is_complete(&contents(true, 3, 0), quiescence, now));
+
+        // Modified in the future.
+        let future = Contents {
+            done: false,
+            frames: 3,
+            modified: now + Duration::from_secs(60),
+        };
+        assert!(!is_complete(&future, quiescence, now));
+    }
+
+    #[test]
+    fn only_images_are_frames() {
+        let scratch = Scratch::new();
+        for name in &["001.png", "002.JPG", "extract.log", DONE_MARKER, ".003.png"] {
+            File::create(scratch.join(name)).unwrap();
+        }
+        fs::create_dir(scratch.join("thumbnails.png")).unwrap();
+
+        let contents = inspect(&scratch).unwrap();
+        assert!(contents.done);
+        assert_eq!(contents.frames, 2);
+    }
+}